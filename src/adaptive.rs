@@ -0,0 +1,119 @@
+use std::fs;
+use std::io;
+use std::io::Write;
+
+use crate::vec3f;
+use crate::checkpoint;
+
+// Keeps sampling a pixel until the standard error of its mean brightness
+// drops below `threshold` relative to that brightness, taking at least
+// `min_samples` and at most `max_samples`
+#[derive(Copy, Clone)]
+pub struct AdaptiveSampling
+{
+    pub min_samples: u32,
+    pub max_samples: u32,
+    pub threshold: f32,
+    // Writes an image of how many samples every pixel took
+    pub heatmap: bool,
+}
+
+// Running mean and variance of a pixel's brightness, using Welford's update
+pub struct PixelStats
+{
+    pub count: u32,
+    mean: f32,
+    m2: f32,
+}
+
+impl PixelStats
+{
+    pub fn new() -> PixelStats
+    {
+        PixelStats
+        {
+            count: 0,
+            mean: 0.0,
+            m2: 0.0,
+        }
+    }
+
+    pub fn add(&mut self, colour: &vec3f::Vec3f32)
+    {
+        let x = (colour.x + colour.y + colour.z) / 3.0;
+        self.count += 1;
+        let delta = x - self.mean;
+        self.mean += delta / self.count as f32;
+        self.m2 += delta * (x - self.mean);
+    }
+
+    pub fn encode(&self, encoder: &mut checkpoint::Encoder)
+    {
+        encoder.u32(self.count);
+        encoder.f32(self.mean);
+        encoder.f32(self.m2);
+    }
+
+    pub fn decode(decoder: &mut checkpoint::Decoder) -> io::Result<PixelStats>
+    {
+        Ok(PixelStats
+        {
+            count: decoder.u32()?,
+            mean: decoder.f32()?,
+            m2: decoder.f32()?,
+        })
+    }
+
+    // Standard error of the mean relative to the mean, softened so that
+    // almost black pixels are not chased forever
+    pub fn relative_error(&self) -> f32
+    {
+        if self.count < 2
+        {
+            return f32::MAX;
+        }
+        let variance = self.m2 / (self.count - 1) as f32;
+        (variance / self.count as f32).sqrt() / (self.mean + 0.05)
+    }
+}
+
+impl AdaptiveSampling
+{
+    pub fn needs_sample(&self, stats: &PixelStats) -> bool
+    {
+        if stats.count < self.min_samples
+        {
+            return true;
+        }
+        stats.count < self.max_samples && stats.relative_error() > self.threshold
+    }
+}
+
+// Blue through green to red as the count goes from none to max_samples
+fn heat(t: f32) -> vec3f::Vec3f32
+{
+    let t = t.clamp(0.0, 1.0);
+    if t < 0.5
+    {
+        vec3f::Vec3f32::new_from_points(0.0, 2.0 * t, 1.0 - 2.0 * t)
+    }
+    else
+    {
+        vec3f::Vec3f32::new_from_points(2.0 * t - 1.0, 2.0 - 2.0 * t, 0.0)
+    }
+}
+
+// Writes the per pixel sample counts, in rows from the top down, as a PPM
+// named after the image
+pub fn write_heatmap(image_path: &str, counts: &[vec3f::Vec3f32], nx: u32, ny: u32,
+                     max_samples: u32)
+{
+    let stem = image_path.strip_suffix(".ppm").unwrap_or(image_path);
+    let mut file = fs::File::create(format!("{}.heatmap.ppm", stem)).unwrap();
+    write!(file, "P3\n {} {}\n255\n", nx, ny).unwrap();
+    for count in counts
+    {
+        let col = heat(count.x / max_samples as f32) * 255.0 + 0.5;
+        col.write_vec_as_int(&mut file);
+    }
+}
@@ -0,0 +1,202 @@
+use crate::vec3f;
+use crate::camera;
+use crate::transform;
+use crate::checkpoint;
+
+#[derive(Copy, Clone)]
+pub enum Interpolation
+{
+    Linear,
+    // Smooth spline passing through every key, with the tangent at each key
+    // taken from its neighbours
+    CatmullRom,
+}
+
+// Values that can be blended between keyframes
+pub trait Interpolate: Copy
+{
+    fn scale_add(&self, other: &Self, a: f32, b: f32) -> Self;
+
+    fn lerp(&self, other: &Self, t: f32) -> Self
+    {
+        self.scale_add(other, 1.0 - t, t)
+    }
+
+    // Uniform Catmull-Rom spline between p1 and p2
+    fn catmull_rom(p0: &Self, p1: &Self, p2: &Self, p3: &Self, t: f32) -> Self
+    {
+        let t2 = t * t;
+        let t3 = t2 * t;
+        let w0 = -0.5 * t3 + t2 - 0.5 * t;
+        let w1 = 1.5 * t3 - 2.5 * t2 + 1.0;
+        let w2 = -1.5 * t3 + 2.0 * t2 + 0.5 * t;
+        let w3 = 0.5 * t3 - 0.5 * t2;
+        p0.scale_add(p1, w0, w1)
+            .scale_add(&p2.scale_add(p3, w2, w3), 1.0, 1.0)
+    }
+}
+
+impl Interpolate for f32
+{
+    fn scale_add(&self, other: &f32, a: f32, b: f32) -> f32
+    {
+        self * a + other * b
+    }
+}
+
+impl Interpolate for vec3f::Vec3f32
+{
+    fn scale_add(&self, other: &vec3f::Vec3f32, a: f32, b: f32) -> vec3f::Vec3f32
+    {
+        *self * a + *other * b
+    }
+}
+
+impl Interpolate for transform::Pose
+{
+    fn scale_add(&self, other: &transform::Pose, a: f32, b: f32) -> transform::Pose
+    {
+        transform::Pose
+        {
+            translation: self.translation.scale_add(&other.translation, a, b),
+            rotation: self.rotation.scale_add(&other.rotation, a, b),
+            scale: self.scale.scale_add(&other.scale, a, b),
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct Keyframe<T>
+{
+    pub time: f32,
+    pub value: T,
+}
+
+// Keyframes sorted by time. Before the first key and after the last the
+// track holds the end values.
+#[derive(Clone)]
+pub struct Track<T>
+{
+    keys: Vec<Keyframe<T>>,
+    interpolation: Interpolation,
+}
+
+impl<T: Interpolate> Track<T>
+{
+    pub fn new(mut keys: Vec<Keyframe<T>>, interpolation: Interpolation) -> Track<T>
+    {
+        assert!(!keys.is_empty(), "a track needs at least one keyframe");
+        keys.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
+        Track { keys, interpolation }
+    }
+
+    pub fn constant(value: T) -> Track<T>
+    {
+        Track::new(vec![Keyframe { time: 0.0, value }], Interpolation::Linear)
+    }
+
+    pub fn sample(&self, time: f32) -> T
+    {
+        let last = self.keys.len() - 1;
+        if time <= self.keys[0].time
+        {
+            return self.keys[0].value;
+        }
+        if time >= self.keys[last].time
+        {
+            return self.keys[last].value;
+        }
+        // Index of the key starting the segment containing `time`
+        let i = self.keys.iter().rposition(|k| k.time <= time).unwrap();
+        let k1 = &self.keys[i];
+        let k2 = &self.keys[i + 1];
+        let t = (time - k1.time) / (k2.time - k1.time);
+        match self.interpolation
+        {
+            Interpolation::Linear => k1.value.lerp(&k2.value, t),
+            Interpolation::CatmullRom =>
+            {
+                // Repeat the end keys so the curve still reaches them
+                let p0 = &self.keys[i.saturating_sub(1)].value;
+                let p3 = &self.keys[(i + 2).min(last)].value;
+                T::catmull_rom(p0, &k1.value, &k2.value, p3, t)
+            },
+        }
+    }
+
+    // Hashes the keys, with `value` hashing what each one holds
+    pub fn fingerprint(&self, fingerprint: &mut checkpoint::Fingerprint,
+                       value: impl Fn(&T, &mut checkpoint::Fingerprint))
+    {
+        fingerprint.u32(self.interpolation as u32);
+        fingerprint.u32(self.keys.len() as u32);
+        for key in &self.keys
+        {
+            fingerprint.f32(key.time);
+            value(&key.value, fingerprint);
+        }
+    }
+}
+
+// The animatable part of a camera description
+#[derive(Copy, Clone)]
+pub struct CameraKey
+{
+    pub look_from: vec3f::Vec3f32,
+    pub look_at: vec3f::Vec3f32,
+    pub fov: f32,
+    pub focus_dist: f32,
+}
+
+impl Interpolate for CameraKey
+{
+    fn scale_add(&self, other: &CameraKey, a: f32, b: f32) -> CameraKey
+    {
+        CameraKey
+        {
+            look_from: self.look_from.scale_add(&other.look_from, a, b),
+            look_at: self.look_at.scale_add(&other.look_at, a, b),
+            fov: self.fov.scale_add(&other.fov, a, b),
+            focus_dist: self.focus_dist.scale_add(&other.focus_dist, a, b),
+        }
+    }
+}
+
+// Renders the scene as a numbered sequence of frames instead of one still
+pub struct Animation
+{
+    pub camera: Track<CameraKey>,
+    pub frames: u32,
+    pub frames_per_second: f32,
+    // Fraction of each frame the shutter is open for, giving motion blur
+    pub shutter_fraction: f32,
+    pub output_dir: String,
+}
+
+impl Animation
+{
+    // Camera description for one frame, with the shutter opening at the
+    // frame's time so that objects animated over scene time blur with it
+    pub fn frame_camera(&self, description: &camera::CameraDescription,
+                        frame: u32) -> camera::CameraDescription
+    {
+        let time = frame as f32 / self.frames_per_second;
+        let key = self.camera.sample(time);
+        let mut frame_description = description.clone();
+        frame_description.look_from = key.look_from;
+        frame_description.look_at = key.look_at;
+        frame_description.fov = key.fov;
+        frame_description.focus_dist = key.focus_dist;
+        frame_description.shutter = camera::Shutter
+        {
+            open: time,
+            close: time + self.shutter_fraction / self.frames_per_second,
+        };
+        frame_description
+    }
+
+    pub fn frame_path(&self, frame: u32) -> String
+    {
+        format!("{}/frame_{:04}.ppm", self.output_dir, frame)
+    }
+}
@@ -0,0 +1,374 @@
+use std::fs;
+use std::io;
+use std::io::Write;
+
+use crate::vec3f;
+use crate::checkpoint;
+use crate::crop;
+
+// Arbitrary output variables: passes written next to the beauty image
+#[derive(Copy, Clone, PartialEq)]
+pub enum Aov
+{
+    // Distance from the camera to the first hit, zero where nothing was hit
+    Depth,
+    // World space normal at the first hit
+    Normal,
+    Albedo,
+    // Index of the first object hit plus one, zero for the sky
+    ObjectId,
+    // Kind of material at the first hit plus one, zero for the sky
+    MaterialId,
+    // World space position of the first hit
+    Position,
+    // Light reaching the camera after at most one bounce
+    Direct,
+    // Light reaching the camera after two bounces or more
+    Indirect,
+    SampleCount,
+}
+
+impl Aov
+{
+    pub fn name(&self) -> &'static str
+    {
+        match *self
+        {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+            Aov::Position => "position",
+            Aov::Direct => "direct",
+            Aov::Indirect => "indirect",
+            Aov::SampleCount => "sample_count",
+        }
+    }
+
+    // The pass with this name, as given on the command line
+    pub fn from_name(name: &str) -> Option<Aov>
+    {
+        match name
+        {
+            "depth" => Some(Aov::Depth),
+            "normal" => Some(Aov::Normal),
+            "albedo" => Some(Aov::Albedo),
+            "object_id" => Some(Aov::ObjectId),
+            "material_id" => Some(Aov::MaterialId),
+            "position" => Some(Aov::Position),
+            "direct" => Some(Aov::Direct),
+            "indirect" => Some(Aov::Indirect),
+            "sample_count" => Some(Aov::SampleCount),
+            _ => None,
+        }
+    }
+
+    fn is_scalar(&self) -> bool
+    {
+        matches!(*self, Aov::Depth | Aov::ObjectId | Aov::MaterialId | Aov::SampleCount)
+    }
+
+    // Ids would turn into meaningless in-between values if averaged, so
+    // they keep the first sample of the pixel
+    fn is_id(&self) -> bool
+    {
+        matches!(*self, Aov::ObjectId | Aov::MaterialId)
+    }
+}
+
+// What a single camera sample saw, filled in by `color` as the path is
+// traced
+pub struct SampleAovs
+{
+    pub hit: bool,
+    pub depth: f32,
+    pub normal: vec3f::Vec3f32,
+    pub albedo: vec3f::Vec3f32,
+    pub position: vec3f::Vec3f32,
+    pub object_id: u32,
+    pub material_id: u32,
+    // Attenuation of the first bounce, and the light given off locally by
+    // the first two vertices of the path (emission or the sky), from which
+    // the direct lighting is rebuilt
+    pub first_attenuation: vec3f::Vec3f32,
+    pub emitted: [vec3f::Vec3f32; 2],
+}
+
+impl SampleAovs
+{
+    pub fn new() -> SampleAovs
+    {
+        SampleAovs
+        {
+            hit: false,
+            depth: 0.0,
+            normal: vec3f::Vec3f32::zeroes(),
+            albedo: vec3f::Vec3f32::zeroes(),
+            position: vec3f::Vec3f32::zeroes(),
+            object_id: 0,
+            material_id: 0,
+            first_attenuation: vec3f::Vec3f32::zeroes(),
+            emitted: [vec3f::Vec3f32::zeroes(); 2],
+        }
+    }
+
+    // Records the light given off at the vertex `depth` bounces along the
+    // path
+    pub fn record_emitted(&mut self, depth: i32, emitted: vec3f::Vec3f32)
+    {
+        if (0 .. 2).contains(&depth)
+        {
+            self.emitted[depth as usize] = emitted;
+        }
+    }
+
+    fn direct(&self) -> vec3f::Vec3f32
+    {
+        self.emitted[0] + self.first_attenuation * self.emitted[1]
+    }
+
+    // Value of a pass for this sample, which contributed `radiance` after
+    // being weighted by the camera with `weight`
+    fn value(&self, aov: Aov, weight: vec3f::Vec3f32,
+             radiance: vec3f::Vec3f32) -> vec3f::Vec3f32
+    {
+        let scalar = |x: f32| vec3f::Vec3f32::new_from_points(x, x, x);
+        match aov
+        {
+            Aov::Depth => scalar(self.depth),
+            Aov::Normal => self.normal,
+            Aov::Albedo => self.albedo,
+            Aov::ObjectId => scalar(self.object_id as f32),
+            Aov::MaterialId => scalar(self.material_id as f32),
+            Aov::Position => self.position,
+            Aov::Direct => weight * self.direct(),
+            Aov::Indirect => radiance - weight * self.direct(),
+            Aov::SampleCount => scalar(1.0),
+        }
+    }
+}
+
+// Per pixel averages of the enabled passes, in rows from the top of the
+// image down
+#[derive(Clone)]
+pub struct AovBuffers
+{
+    nx: u32,
+    ny: u32,
+    passes: Vec<(Aov, Vec<vec3f::Vec3f32>)>,
+    counts: Vec<u32>,
+}
+
+impl AovBuffers
+{
+    pub fn new(nx: u32, ny: u32, aovs: &[Aov]) -> AovBuffers
+    {
+        AovBuffers
+        {
+            nx,
+            ny,
+            passes: aovs.iter()
+                .map(|&aov| (aov, vec![vec3f::Vec3f32::zeroes(); (nx * ny) as usize]))
+                .collect(),
+            counts: vec![0; (nx * ny) as usize],
+        }
+    }
+
+    // Adds a sample taken in pixel column i and row j, counting rows up
+    // from the bottom of the image like the film does
+    pub fn add_sample(&mut self, i: u32, j: u32, sample: &SampleAovs,
+                      weight: vec3f::Vec3f32, radiance: vec3f::Vec3f32)
+    {
+        let index = ((self.ny - 1 - j) * self.nx + i) as usize;
+        let count = self.counts[index];
+        for (aov, values) in &mut self.passes
+        {
+            let value = sample.value(*aov, weight, radiance);
+            if aov.is_id()
+            {
+                if count == 0
+                {
+                    values[index] = value;
+                }
+            }
+            else if *aov == Aov::SampleCount
+            {
+                values[index] += value;
+            }
+            else
+            {
+                // Running mean, so the buffers always hold finished values
+                let mean = values[index];
+                values[index] = mean + (value - mean) / (count + 1) as f32;
+            }
+        }
+        self.counts[index] = count + 1;
+    }
+
+    pub fn encode(&self, encoder: &mut checkpoint::Encoder)
+    {
+        for (_, values) in &self.passes
+        {
+            for value in values
+            {
+                encoder.vec3(value);
+            }
+        }
+        for count in &self.counts
+        {
+            encoder.u32(*count);
+        }
+    }
+
+    pub fn decode(&mut self, decoder: &mut checkpoint::Decoder) -> io::Result<()>
+    {
+        for (_, values) in &mut self.passes
+        {
+            for value in values
+            {
+                *value = decoder.vec3()?;
+            }
+        }
+        for count in &mut self.counts
+        {
+            *count = decoder.u32()?;
+        }
+        Ok(())
+    }
+
+    pub fn crop(&self, window: &crop::CropWindow) -> AovBuffers
+    {
+        AovBuffers
+        {
+            nx: window.width,
+            ny: window.height,
+            passes: self.passes.iter()
+                .map(|(aov, values)| (*aov, window.extract(values, self.nx)))
+                .collect(),
+            counts: window.extract(&self.counts, self.nx),
+        }
+    }
+
+    // Stacks another set of buffers of the same width and passes below
+    // these ones, as for the two eyes of a stereo pair
+    pub fn extend(&mut self, other: AovBuffers)
+    {
+        for ((_, values), (_, other_values)) in self.passes.iter_mut().zip(other.passes)
+        {
+            values.extend(other_values);
+        }
+        self.counts.extend(other.counts);
+        self.ny += other.ny;
+    }
+
+    // Just the given passes, dropping the ones that were only gathered for
+    // use while rendering
+    pub fn only(self, aovs: &[Aov]) -> AovBuffers
+    {
+        AovBuffers
+        {
+            passes: self.passes.into_iter().filter(|(aov, _)| aovs.contains(aov)).collect(),
+            ..self
+        }
+    }
+
+    pub fn pass(&self, aov: Aov) -> Option<&[vec3f::Vec3f32]>
+    {
+        self.passes.iter()
+            .find(|(a, _)| *a == aov)
+            .map(|(_, values)| values.as_slice())
+    }
+
+    // Writes every pass as a little endian PFM file, named after the image
+    // with the pass name inserted before the extension
+    pub fn write(&self, image_path: &str) -> io::Result<()>
+    {
+        let stem = image_path.strip_suffix(".ppm").unwrap_or(image_path);
+        for (aov, values) in &self.passes
+        {
+            let mut file = fs::File::create(format!("{}.{}.pfm", stem, aov.name()))?;
+            let magic = if aov.is_scalar() { "Pf" } else { "PF" };
+            write!(file, "{}\n{} {}\n-1.0\n", magic, self.nx, self.ny)?;
+            let mut bytes = Vec::with_capacity(values.len() * 12);
+            // PFM stores the bottom row first
+            for row in values.chunks(self.nx as usize).rev()
+            {
+                for value in row
+                {
+                    bytes.extend_from_slice(&value.x.to_le_bytes());
+                    if !aov.is_scalar()
+                    {
+                        bytes.extend_from_slice(&value.y.to_le_bytes());
+                        bytes.extend_from_slice(&value.z.to_le_bytes());
+                    }
+                }
+            }
+            file.write_all(&bytes)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn sample(object_id: u32, depth: f32) -> SampleAovs
+    {
+        SampleAovs { hit: true, depth, object_id, ..SampleAovs::new() }
+    }
+
+    fn add(buffers: &mut AovBuffers, i: u32, j: u32, sample: &SampleAovs)
+    {
+        buffers.add_sample(i, j, sample, vec3f::Vec3f32::new_from_points(1.0, 1.0, 1.0),
+                           vec3f::Vec3f32::zeroes());
+    }
+
+    #[test]
+    fn rows_are_stored_from_the_top_down()
+    {
+        let mut buffers = AovBuffers::new(2, 2, &[Aov::Depth]);
+        // Film row 0 is the bottom of the image
+        add(&mut buffers, 1, 0, &sample(1, 5.0));
+        add(&mut buffers, 0, 1, &sample(1, 7.0));
+        let depth: Vec<f32> = buffers.pass(Aov::Depth).unwrap().iter().map(|v| v.x).collect();
+        assert_eq!(depth, vec![7.0, 0.0, 0.0, 5.0]);
+    }
+
+    #[test]
+    fn ids_keep_the_first_sample_and_others_average()
+    {
+        let mut buffers = AovBuffers::new(2, 2, &[Aov::ObjectId, Aov::Depth, Aov::SampleCount]);
+        add(&mut buffers, 0, 0, &sample(3, 2.0));
+        add(&mut buffers, 0, 0, &sample(8, 4.0));
+        add(&mut buffers, 0, 0, &sample(5, 9.0));
+        assert_eq!(buffers.pass(Aov::ObjectId).unwrap()[2].x, 3.0);
+        assert_eq!(buffers.pass(Aov::Depth).unwrap()[2].x, 5.0);
+        assert_eq!(buffers.pass(Aov::SampleCount).unwrap()[2].x, 3.0);
+    }
+
+    #[test]
+    fn pfm_stores_the_bottom_row_first()
+    {
+        let mut buffers = AovBuffers::new(2, 2, &[Aov::Depth]);
+        add(&mut buffers, 0, 0, &sample(1, 1.0));
+        add(&mut buffers, 1, 0, &sample(1, 2.0));
+        add(&mut buffers, 0, 1, &sample(1, 3.0));
+        add(&mut buffers, 1, 1, &sample(1, 4.0));
+        let path = std::env::temp_dir().join(format!("aov_test_{}.ppm", std::process::id()));
+        let path = path.to_str().unwrap();
+        buffers.write(path).unwrap();
+        let pfm_path = format!("{}.depth.pfm", path.strip_suffix(".ppm").unwrap());
+        let bytes = fs::read(&pfm_path).unwrap();
+        fs::remove_file(&pfm_path).unwrap();
+
+        let header = b"Pf\n2 2\n-1.0\n";
+        assert_eq!(&bytes[.. header.len()], header);
+        let values: Vec<f32> = bytes[header.len() ..].chunks(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        assert_eq!(values, vec![1.0, 2.0, 3.0, 4.0]);
+    }
+}
@@ -0,0 +1,144 @@
+use std::sync::OnceLock;
+
+// Side of the square, tileable mask
+pub const SIZE: usize = 64;
+
+const SIGMA: f32 = 1.9;
+
+// Fraction of the pixels set in the starting pattern
+const INITIAL_DENSITY: f32 = 0.1;
+
+// Gaussian energy every set pixel spreads over its neighbours, with
+// distances wrapping around so the mask tiles
+struct Energy
+{
+    kernel: Vec<f32>,
+    values: Vec<f32>,
+}
+
+impl Energy
+{
+    fn new() -> Energy
+    {
+        let mut kernel = vec![0.0; SIZE * SIZE];
+        for dy in 0 .. SIZE
+        {
+            for dx in 0 .. SIZE
+            {
+                let wx = dx.min(SIZE - dx) as f32;
+                let wy = dy.min(SIZE - dy) as f32;
+                kernel[dy * SIZE + dx] = (-(wx * wx + wy * wy) / (2.0 * SIGMA * SIGMA)).exp();
+            }
+        }
+        Energy
+        {
+            kernel,
+            values: vec![0.0; SIZE * SIZE],
+        }
+    }
+
+    fn splat(&mut self, pixel: usize, sign: f32)
+    {
+        let (px, py) = (pixel % SIZE, pixel / SIZE);
+        for y in 0 .. SIZE
+        {
+            for x in 0 .. SIZE
+            {
+                let dx = (x + SIZE - px) % SIZE;
+                let dy = (y + SIZE - py) % SIZE;
+                self.values[y * SIZE + x] += sign * self.kernel[dy * SIZE + dx];
+            }
+        }
+    }
+
+    // The set pixel with the most energy, the centre of the tightest
+    // cluster, or the unset pixel with the least, the middle of the largest
+    // void
+    fn extreme(&self, pattern: &[bool], set: bool) -> usize
+    {
+        let candidates = (0 .. SIZE * SIZE).filter(|&p| pattern[p] == set);
+        if set
+        {
+            candidates.max_by(|&a, &b| self.values[a].total_cmp(&self.values[b])).unwrap()
+        }
+        else
+        {
+            candidates.min_by(|&a, &b| self.values[a].total_cmp(&self.values[b])).unwrap()
+        }
+    }
+}
+
+// Ulichney's void-and-cluster method: every pixel gets a rank such that the
+// pixels below any threshold are spread out as evenly as possible. Returns
+// the ranks scaled into (0, 1), in rows.
+fn void_and_cluster() -> Vec<f32>
+{
+    let count = SIZE * SIZE;
+    let mut pattern = vec![false; count];
+    let mut energy = Energy::new();
+
+    // A fixed pseudo-random starting pattern, so every run gets the same mask
+    let initial = (count as f32 * INITIAL_DENSITY) as usize;
+    let mut state = 0x2545_f491u32;
+    let mut placed = 0;
+    while placed < initial
+    {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        let pixel = state as usize % count;
+        if !pattern[pixel]
+        {
+            pattern[pixel] = true;
+            energy.splat(pixel, 1.0);
+            placed += 1;
+        }
+    }
+
+    // Move pixels out of clusters into voids until that stops changing
+    // anything
+    loop
+    {
+        let cluster = energy.extreme(&pattern, true);
+        pattern[cluster] = false;
+        energy.splat(cluster, -1.0);
+        let void = energy.extreme(&pattern, false);
+        pattern[void] = true;
+        energy.splat(void, 1.0);
+        if void == cluster
+        {
+            break;
+        }
+    }
+
+    let mut ranks = vec![0; count];
+
+    // Ranks below the starting pattern: take out the tightest clusters
+    let mut thinned = pattern.clone();
+    let mut thinned_energy = Energy { kernel: energy.kernel.clone(), values: energy.values.clone() };
+    for rank in (0 .. initial).rev()
+    {
+        let cluster = thinned_energy.extreme(&thinned, true);
+        thinned[cluster] = false;
+        thinned_energy.splat(cluster, -1.0);
+        ranks[cluster] = rank;
+    }
+
+    // Ranks above it: fill the largest voids
+    for rank in initial .. count
+    {
+        let void = energy.extreme(&pattern, false);
+        pattern[void] = true;
+        energy.splat(void, 1.0);
+        ranks[void] = rank;
+    }
+
+    ranks.iter().map(|&rank| (rank as f32 + 0.5) / count as f32).collect()
+}
+
+// The mask, made the first time it is asked for
+pub fn mask() -> &'static [f32]
+{
+    static MASK: OnceLock<Vec<f32>> = OnceLock::new();
+    MASK.get_or_init(void_and_cluster)
+}
@@ -0,0 +1,221 @@
+use std::io;
+
+use crate::vec3f;
+use crate::rng;
+use crate::pgm;
+use crate::checkpoint;
+
+// Uniform over the unit disk from exactly two draws
+fn random_in_unit_disk() -> vec3f::Vec3f32
+{
+    let radius = rng::random().sqrt();
+    let theta = 2.0 * std::f32::consts::PI * rng::random();
+    vec3f::Vec3f32::new_from_points(radius * theta.cos(), radius * theta.sin(), 0.0)
+}
+
+// A grayscale image of the aperture, where white lets light through and
+// black blocks it. The image is stretched over the square bounding the
+// unit lens disk.
+#[derive(Clone)]
+pub struct ApertureMask
+{
+    width: usize,
+    height: usize,
+    values: Vec<f32>,
+}
+
+impl ApertureMask
+{
+    pub fn from_pgm(path: &str) -> io::Result<ApertureMask>
+    {
+        let (width, height, values) = pgm::read(path)?;
+        if values.iter().all(|&v| v <= 0.0)
+        {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      "aperture mask is completely black"));
+        }
+        Ok(ApertureMask { width, height, values })
+    }
+
+    // Transmission at a point of the square [-1, 1]^2, with +y at the top
+    // of the image
+    fn transmission(&self, x: f32, y: f32) -> f32
+    {
+        let i = (((x + 1.0) * 0.5 * self.width as f32) as usize).min(self.width - 1);
+        let j = (((1.0 - y) * 0.5 * self.height as f32) as usize).min(self.height - 1);
+        self.values[j * self.width + i]
+    }
+}
+
+#[derive(Clone)]
+pub enum ApertureShape
+{
+    Circle,
+    // Regular polygon inscribed in the lens disk, as formed by a diaphragm
+    // with this many straight blades, rotated by `rotation` degrees
+    Polygon
+    {
+        blades: u32,
+        rotation: f32,
+    },
+    Mask(ApertureMask),
+}
+
+impl ApertureShape
+{
+    // Uniformly samples a point of the aperture, scaled so that the lens
+    // disk has radius one
+    pub fn sample(&self) -> (f32, f32)
+    {
+        match *self
+        {
+            ApertureShape::Circle =>
+            {
+                let p = random_in_unit_disk();
+                (p.x, p.y)
+            },
+
+            ApertureShape::Polygon { blades, rotation } =>
+            {
+                // Every blade edge forms an equal triangle with the centre,
+                // so pick one and sample it uniformly
+                let blades = blades.max(3);
+                let wedge = 2.0 * std::f32::consts::PI / blades as f32;
+                let k = (rng::random() * blades as f32) as u32 % blades;
+                let a0 = rotation.to_radians() + wedge * k as f32;
+                let a1 = a0 + wedge;
+                let mut s = rng::random();
+                let mut t = rng::random();
+                if s + t > 1.0
+                {
+                    s = 1.0 - s;
+                    t = 1.0 - t;
+                }
+                (s * a0.cos() + t * a1.cos(), s * a0.sin() + t * a1.sin())
+            },
+
+            ApertureShape::Mask(ref mask) =>
+            {
+                // Rejection sample the square against the mask, giving up on
+                // the centre if the mask lets almost nothing through
+                for _ in 0 .. 256
+                {
+                    let x = 2.0 * rng::random() - 1.0;
+                    let y = 2.0 * rng::random() - 1.0;
+                    if rng::random() < mask.transmission(x, y)
+                    {
+                        return (x, y);
+                    }
+                }
+                (0.0, 0.0)
+            },
+        }
+    }
+
+    pub fn fingerprint(&self, fingerprint: &mut checkpoint::Fingerprint)
+    {
+        match *self
+        {
+            ApertureShape::Circle => fingerprint.u32(0),
+            ApertureShape::Polygon { blades, rotation } =>
+            {
+                fingerprint.u32(1);
+                fingerprint.u32(blades);
+                fingerprint.f32(rotation);
+            },
+            ApertureShape::Mask(ref mask) =>
+            {
+                fingerprint.u32(2);
+                fingerprint.u32(mask.width as u32);
+                fingerprint.u32(mask.height as u32);
+                for &value in &mask.values
+                {
+                    fingerprint.f32(value);
+                }
+            },
+        }
+    }
+}
+
+// Lateral and longitudinal colour fringing. Red and blue are pushed in
+// opposite directions by these amounts, green is left where it is.
+#[derive(Copy, Clone)]
+pub struct ChromaticAberration
+{
+    // Relative change of image scale
+    pub lateral: f32,
+    // Relative change of focus distance
+    pub longitudinal: f32,
+}
+
+#[derive(Clone)]
+pub struct LensEffects
+{
+    pub aperture: ApertureShape,
+    // How strongly the lens barrel clips the aperture towards the edges of
+    // the frame. Zero disables it, one clips the corners down to nothing.
+    pub cat_eye: f32,
+    pub chromatic_aberration: Option<ChromaticAberration>,
+}
+
+impl LensEffects
+{
+    pub fn none() -> LensEffects
+    {
+        LensEffects
+        {
+            aperture: ApertureShape::Circle,
+            cat_eye: 0.0,
+            chromatic_aberration: None,
+        }
+    }
+
+    // Whether a lens point survives the barrel vignetting for a pixel at
+    // x, y in [-1, 1]. Off axis the barrel acts as a second circular stop
+    // shifted away from the centre, leaving the cat's eye shaped overlap.
+    pub fn passes_barrel(&self, lens_x: f32, lens_y: f32, x: f32, y: f32) -> bool
+    {
+        if self.cat_eye <= 0.0
+        {
+            return true;
+        }
+        let dx = lens_x - x * self.cat_eye;
+        let dy = lens_y - y * self.cat_eye;
+        dx * dx + dy * dy <= 1.0
+    }
+
+    // Picks a colour channel for the ray when chromatic aberration is on,
+    // returning the image scale, the focus distance scale and the weight
+    // of the ray in each channel
+    pub fn sample_channel(&self) -> (f32, f32, vec3f::Vec3f32)
+    {
+        match self.chromatic_aberration
+        {
+            None => (1.0, 1.0, vec3f::Vec3f32::new_from_points(1.0, 1.0, 1.0)),
+            Some(aberration) =>
+            {
+                let channel = (rng::random() * 3.0) as u32;
+                let (shift, weight) = match channel
+                {
+                    0 => (1.0, vec3f::Vec3f32::new_from_points(3.0, 0.0, 0.0)),
+                    1 => (0.0, vec3f::Vec3f32::new_from_points(0.0, 3.0, 0.0)),
+                    _ => (-1.0, vec3f::Vec3f32::new_from_points(0.0, 0.0, 3.0)),
+                };
+                (1.0 + shift * aberration.lateral,
+                 1.0 + shift * aberration.longitudinal,
+                 weight)
+            },
+        }
+    }
+
+    pub fn fingerprint(&self, fingerprint: &mut checkpoint::Fingerprint)
+    {
+        self.aperture.fingerprint(fingerprint);
+        fingerprint.f32(self.cat_eye);
+        if let Some(ref aberration) = self.chromatic_aberration
+        {
+            fingerprint.f32(aberration.lateral);
+            fingerprint.f32(aberration.longitudinal);
+        }
+    }
+}
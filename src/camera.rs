@@ -1,23 +1,63 @@
 use crate::vec3f;
+use crate::rng;
 use crate::ray;
+use crate::bokeh;
+use crate::lens;
+use crate::checkpoint;
 
-use rand;
+// Orthonormal camera frame: w points backwards from the view direction, u
+// to the right and v up
+pub fn camera_frame(look_from: vec3f::Vec3f32, look_at: vec3f::Vec3f32,
+                vup: vec3f::Vec3f32) -> (vec3f::Vec3f32, vec3f::Vec3f32, vec3f::Vec3f32)
+{
+    let w = (look_from - look_at).unit_vector();
+    let u = (vup.cross_product(&w)).unit_vector();
+    let v = w.cross_product(&u);
+    (u, v, w)
+}
+
+// Interval the shutter is open for, rays are spread uniformly across it
+#[derive(Copy, Clone)]
+pub struct Shutter
+{
+    pub open: f32,
+    pub close: f32,
+}
 
-fn random_in_unit_disk() -> vec3f::Vec3f32
+impl Shutter
 {
-    let mut p = vec3f::Vec3f32::new_from_points(1.5, 1.5, 1.5);
-    while p.dot_product(&p) >= 1.0
+    pub fn instant() -> Shutter
+    {
+        Shutter
+        {
+            open: 0.0,
+            close: 0.0,
+        }
+    }
+
+    pub fn sample(&self) -> f32
     {
-        p = (vec3f::Vec3f32::new_from_points(rand::random::<f32>(),
-                                             rand::random::<f32>(),
-                                             rand::random::<f32>()) * 2.0) -
-            vec3f::Vec3f32::new_from_points(1.0, 1.0, 0.0);
+        self.open + rng::random() * (self.close - self.open)
+    }
+}
 
+// Maps image coordinates u and v in [0, 1], with v = 0 at the bottom of
+// the image, to a primary ray. None means the point lies outside the area
+// the projection covers and sees nothing.
+pub trait Camera
+{
+    fn get_ray(&self, u: f32, v: f32) -> Option<ray::Ray>;
+
+    // Like get_ray, but also returns how much the ray counts towards each
+    // colour channel, for cameras that split colours apart
+    fn get_weighted_ray(&self, u: f32, v: f32) -> Option<(ray::Ray, vec3f::Vec3f32)>
+    {
+        self.get_ray(u, v)
+            .map(|r| (r, vec3f::Vec3f32::new_from_points(1.0, 1.0, 1.0)))
     }
-    p
 }
 
-pub struct Camera
+pub struct ThinLensCamera
 {
     origin: vec3f::Vec3f32,
     lower_left_corner: vec3f::Vec3f32,
@@ -27,47 +67,366 @@ pub struct Camera
     v: vec3f::Vec3f32,
     w: vec3f::Vec3f32,
     lens_radius: f32,
+    lens: bokeh::LensEffects,
+    shutter: Shutter,
 }
 
-impl Camera
+impl ThinLensCamera
 {
     pub fn new(look_from: vec3f::Vec3f32, look_at: vec3f::Vec3f32,
                vup: vec3f::Vec3f32, vfov: f32, aspect: f32,
-               aperature: f32, focus_dist: f32) -> Camera
+               aperature: f32, focus_dist: f32) -> ThinLensCamera
     {
-        let u: vec3f::Vec3f32;
-        let v: vec3f::Vec3f32;
-        let w: vec3f::Vec3f32;
         let theta = vfov * std::f32::consts::PI / 180.0;
         let half_height = (theta / 2.0).tan();
         let half_width = aspect * half_height;
-        w = (look_from - look_at).unit_vector();
-        u = (vup.cross_product(&w)).unit_vector();
-        v = w.cross_product(&u);
-        Camera
+        let (u, v, w) = camera_frame(look_from, look_at, vup);
+        ThinLensCamera
         {
             origin: look_from,
             lower_left_corner: look_from - u * (focus_dist * half_width)
                 - v * (focus_dist * half_height) - w * focus_dist,
             horizontal: u * (focus_dist * (2.0 * half_width)),
             vertical: v * (focus_dist * (2.0 * half_height)),
-            u: u,
-            v: v,
-            w: w,
-            lens_radius: aperature / 2.0
+            u,
+            v,
+            w,
+            lens_radius: aperature / 2.0,
+            lens: bokeh::LensEffects::none(),
+            shutter: Shutter::instant(),
         }
     }
 
+    pub fn with_shutter(mut self, time0: f32, time1: f32) -> ThinLensCamera
+    {
+        self.shutter = Shutter { open: time0, close: time1 };
+        self
+    }
+
+    pub fn with_lens_effects(mut self, lens: bokeh::LensEffects) -> ThinLensCamera
+    {
+        self.lens = lens;
+        self
+    }
+
+    // Slides the image window sideways across the plane of focus by this
+    // fraction of its width without turning the camera, giving an off-axis
+    // frustum
+    pub fn with_horizontal_shift(mut self, fraction: f32) -> ThinLensCamera
+    {
+        self.lower_left_corner += self.horizontal * fraction;
+        self
+    }
+
     fn direction_from_camera(&self, u: f32, v: f32) -> vec3f::Vec3f32
     {
         self.lower_left_corner + self.horizontal * u +
             self.vertical * v - self.origin
     }
+}
+
+impl Camera for ThinLensCamera
+{
+    fn get_ray(&self, u: f32, v: f32) -> Option<ray::Ray>
+    {
+        self.get_weighted_ray(u, v).map(|(r, _)| r)
+    }
+
+    fn get_weighted_ray(&self, u: f32, v: f32) -> Option<(ray::Ray, vec3f::Vec3f32)>
+    {
+        let (image_scale, focus_scale, weight) = self.lens.sample_channel();
+        let u = 0.5 + (u - 0.5) * image_scale;
+        let v = 0.5 + (v - 0.5) * image_scale;
+        let (lens_x, lens_y) = self.lens.aperture.sample();
+        if !self.lens.passes_barrel(lens_x, lens_y, 2.0 * u - 1.0, 2.0 * v - 1.0)
+        {
+            return None;
+        }
+        let offset = (self.u * lens_x + self.v * lens_y) * self.lens_radius;
+        // Every ray through the pixel converges on the same point of the
+        // plane of focus, which moves with the colour under longitudinal
+        // aberration
+        let focus = self.origin + self.direction_from_camera(u, v) * focus_scale;
+        let origin = self.origin + offset;
+        Some((ray::Ray::new_at_time(&origin, &(focus - origin), self.shutter.sample()),
+              weight))
+    }
+}
+
+// Parallel projection: every ray points straight down the view direction
+// from its own spot on an image plane `height` world units tall
+pub struct OrthographicCamera
+{
+    lower_left_corner: vec3f::Vec3f32,
+    horizontal: vec3f::Vec3f32,
+    vertical: vec3f::Vec3f32,
+    w: vec3f::Vec3f32,
+    shutter: Shutter,
+}
+
+impl OrthographicCamera
+{
+    pub fn new(look_from: vec3f::Vec3f32, look_at: vec3f::Vec3f32,
+               vup: vec3f::Vec3f32, height: f32,
+               aspect: f32) -> OrthographicCamera
+    {
+        let (u, v, w) = camera_frame(look_from, look_at, vup);
+        let horizontal = u * (height * aspect);
+        let vertical = v * height;
+        OrthographicCamera
+        {
+            lower_left_corner: look_from - horizontal * 0.5 - vertical * 0.5,
+            horizontal,
+            vertical,
+            w,
+            shutter: Shutter::instant(),
+        }
+    }
+
+    pub fn with_shutter(mut self, time0: f32, time1: f32) -> OrthographicCamera
+    {
+        self.shutter = Shutter { open: time0, close: time1 };
+        self
+    }
+}
 
-    pub fn get_ray(&self, u: f32, v: f32) -> ray::Ray
+impl Camera for OrthographicCamera
+{
+    fn get_ray(&self, u: f32, v: f32) -> Option<ray::Ray>
     {
-        let rd = random_in_unit_disk() * self.lens_radius;
-        let offset = self.u * rd.x + self.v * rd.y;
-        ray::Ray::new_from_vector(&(self.origin + offset), &(self.direction_from_camera(u, v) - offset))
+        let origin = self.lower_left_corner + self.horizontal * u + self.vertical * v;
+        Some(ray::Ray::new_at_time(&origin, &(-self.w), self.shutter.sample()))
+    }
+}
+
+// How a fisheye lens maps the angle off the optical axis to the distance
+// from the centre of the image circle
+#[derive(Copy, Clone)]
+pub enum FisheyeMapping
+{
+    // Distance proportional to the angle
+    Equidistant,
+    // Distance proportional to sin(angle / 2), preserving solid angle
+    Equisolid,
+}
+
+// Fisheye lens whose image circle touches the top and bottom of the frame
+// and spans `fov` degrees across its diameter
+pub struct FisheyeCamera
+{
+    origin: vec3f::Vec3f32,
+    u: vec3f::Vec3f32,
+    v: vec3f::Vec3f32,
+    w: vec3f::Vec3f32,
+    half_fov: f32,
+    aspect: f32,
+    mapping: FisheyeMapping,
+    shutter: Shutter,
+}
+
+impl FisheyeCamera
+{
+    pub fn new(look_from: vec3f::Vec3f32, look_at: vec3f::Vec3f32,
+               vup: vec3f::Vec3f32, fov: f32, aspect: f32,
+               mapping: FisheyeMapping) -> FisheyeCamera
+    {
+        let (u, v, w) = camera_frame(look_from, look_at, vup);
+        FisheyeCamera
+        {
+            origin: look_from,
+            u,
+            v,
+            w,
+            half_fov: fov.min(360.0).to_radians() / 2.0,
+            aspect,
+            mapping,
+            shutter: Shutter::instant(),
+        }
+    }
+
+    pub fn with_shutter(mut self, time0: f32, time1: f32) -> FisheyeCamera
+    {
+        self.shutter = Shutter { open: time0, close: time1 };
+        self
+    }
+}
+
+impl Camera for FisheyeCamera
+{
+    fn get_ray(&self, u: f32, v: f32) -> Option<ray::Ray>
+    {
+        let x = (2.0 * u - 1.0) * self.aspect;
+        let y = 2.0 * v - 1.0;
+        let radius = (x * x + y * y).sqrt();
+        if radius > 1.0
+        {
+            return None;
+        }
+        let theta = match self.mapping
+        {
+            FisheyeMapping::Equidistant => radius * self.half_fov,
+            FisheyeMapping::Equisolid =>
+                2.0 * (radius * (self.half_fov / 2.0).sin()).asin(),
+        };
+        let phi = y.atan2(x);
+        let direction = (self.u * phi.cos() + self.v * phi.sin()) * theta.sin()
+            - self.w * theta.cos();
+        Some(ray::Ray::new_at_time(&self.origin, &direction, self.shutter.sample()))
+    }
+}
+
+// Full sphere latitude/longitude panorama: u sweeps 360 degrees of
+// longitude and v 180 degrees of latitude, with the view direction in the
+// centre of the image
+pub struct EquirectangularCamera
+{
+    origin: vec3f::Vec3f32,
+    u: vec3f::Vec3f32,
+    v: vec3f::Vec3f32,
+    w: vec3f::Vec3f32,
+    shutter: Shutter,
+}
+
+impl EquirectangularCamera
+{
+    pub fn new(look_from: vec3f::Vec3f32, look_at: vec3f::Vec3f32,
+               vup: vec3f::Vec3f32) -> EquirectangularCamera
+    {
+        let (u, v, w) = camera_frame(look_from, look_at, vup);
+        EquirectangularCamera
+        {
+            origin: look_from,
+            u,
+            v,
+            w,
+            shutter: Shutter::instant(),
+        }
+    }
+
+    pub fn with_shutter(mut self, time0: f32, time1: f32) -> EquirectangularCamera
+    {
+        self.shutter = Shutter { open: time0, close: time1 };
+        self
+    }
+}
+
+impl Camera for EquirectangularCamera
+{
+    fn get_ray(&self, u: f32, v: f32) -> Option<ray::Ray>
+    {
+        let longitude = (u - 0.5) * 2.0 * std::f32::consts::PI;
+        let latitude = (v - 0.5) * std::f32::consts::PI;
+        let direction = (self.u * longitude.sin() - self.w * longitude.cos())
+            * latitude.cos() + self.v * latitude.sin();
+        Some(ray::Ray::new_at_time(&self.origin, &direction, self.shutter.sample()))
+    }
+}
+
+#[derive(Clone)]
+pub enum Projection
+{
+    // Thin lens perspective, `fov` is the vertical field of view
+    Perspective,
+    // Parallel projection of an image plane this many world units tall
+    Orthographic(f32),
+    // Fisheye lens, `fov` is the angle across the image circle
+    Fisheye(FisheyeMapping),
+    // 360 by 180 degree panorama, `fov` is unused
+    Equirectangular,
+    // Traced through a real lens prescription onto a film with the given
+    // diagonal in millimetres, which together set the field of view
+    Realistic
+    {
+        lens: lens::LensSystem,
+        film_diagonal: f32,
+    },
+}
+
+// Everything the scene says about its camera, from which the camera for
+// the chosen projection is built
+#[derive(Clone)]
+pub struct CameraDescription
+{
+    pub projection: Projection,
+    pub look_from: vec3f::Vec3f32,
+    pub look_at: vec3f::Vec3f32,
+    pub vup: vec3f::Vec3f32,
+    pub fov: f32,
+    pub aspect: f32,
+    pub aperature: f32,
+    pub focus_dist: f32,
+    // Only the perspective projection has a lens to shape
+    pub lens: bokeh::LensEffects,
+    pub shutter: Shutter,
+}
+
+impl CameraDescription
+{
+    // Fails when a realistic lens cannot focus at the focus distance
+    pub fn build(&self) -> Result<Box<dyn Camera>, String>
+    {
+        let (open, close) = (self.shutter.open, self.shutter.close);
+        match self.projection
+        {
+            Projection::Perspective =>
+                Ok(Box::new(ThinLensCamera::new(self.look_from, self.look_at, self.vup,
+                                             self.fov, self.aspect, self.aperature,
+                                             self.focus_dist)
+                         .with_lens_effects(self.lens.clone())
+                         .with_shutter(open, close))),
+            Projection::Orthographic(height) =>
+                Ok(Box::new(OrthographicCamera::new(self.look_from, self.look_at,
+                                                 self.vup, height, self.aspect)
+                         .with_shutter(open, close))),
+            Projection::Fisheye(mapping) =>
+                Ok(Box::new(FisheyeCamera::new(self.look_from, self.look_at, self.vup,
+                                            self.fov, self.aspect, mapping)
+                         .with_shutter(open, close))),
+            Projection::Equirectangular =>
+                Ok(Box::new(EquirectangularCamera::new(self.look_from, self.look_at,
+                                                    self.vup)
+                         .with_shutter(open, close))),
+            Projection::Realistic { ref lens, film_diagonal } =>
+                Ok(Box::new(lens::RealisticCamera::new(self.look_from, self.look_at,
+                                                    self.vup, lens.clone(),
+                                                    film_diagonal, self.aspect,
+                                                    self.focus_dist)?
+                         .with_shutter(open, close))),
+        }
+    }
+
+    pub fn fingerprint(&self, fingerprint: &mut checkpoint::Fingerprint)
+    {
+        match self.projection
+        {
+            Projection::Perspective => fingerprint.u32(0),
+            Projection::Orthographic(height) =>
+            {
+                fingerprint.u32(1);
+                fingerprint.f32(height);
+            },
+            Projection::Fisheye(mapping) =>
+            {
+                fingerprint.u32(2);
+                fingerprint.u32(mapping as u32);
+            },
+            Projection::Equirectangular => fingerprint.u32(3),
+            Projection::Realistic { ref lens, film_diagonal } =>
+            {
+                fingerprint.u32(4);
+                lens.fingerprint(fingerprint);
+                fingerprint.f32(film_diagonal);
+            },
+        }
+        fingerprint.vec3(&self.look_from);
+        fingerprint.vec3(&self.look_at);
+        fingerprint.vec3(&self.vup);
+        fingerprint.f32(self.fov);
+        fingerprint.f32(self.aspect);
+        fingerprint.f32(self.aperature);
+        fingerprint.f32(self.focus_dist);
+        self.lens.fingerprint(fingerprint);
+        fingerprint.f32(self.shutter.open);
+        fingerprint.f32(self.shutter.close);
     }
 }
@@ -0,0 +1,256 @@
+use std::fs;
+use std::io;
+
+use crate::vec3f;
+
+const MAGIC: &[u8; 8] = b"RAYCKPT1";
+
+// Little endian binary encoding of render state
+pub struct Encoder
+{
+    bytes: Vec<u8>,
+}
+
+impl Encoder
+{
+    pub fn new() -> Encoder
+    {
+        Encoder { bytes: MAGIC.to_vec() }
+    }
+
+    pub fn u32(&mut self, x: u32)
+    {
+        self.bytes.extend_from_slice(&x.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, x: u64)
+    {
+        self.bytes.extend_from_slice(&x.to_le_bytes());
+    }
+
+    pub fn f32(&mut self, x: f32)
+    {
+        self.bytes.extend_from_slice(&x.to_le_bytes());
+    }
+
+    pub fn vec3(&mut self, v: &vec3f::Vec3f32)
+    {
+        self.f32(v.x);
+        self.f32(v.y);
+        self.f32(v.z);
+    }
+}
+
+pub struct Decoder
+{
+    bytes: Vec<u8>,
+    position: usize,
+}
+
+fn invalid(message: &str) -> io::Error
+{
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl Decoder
+{
+    fn take<const N: usize>(&mut self) -> io::Result<[u8; N]>
+    {
+        let bytes = self.bytes.get(self.position .. self.position + N)
+            .ok_or_else(|| invalid("checkpoint is truncated"))?;
+        self.position += N;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    pub fn u32(&mut self) -> io::Result<u32>
+    {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    pub fn u64(&mut self) -> io::Result<u64>
+    {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+
+    pub fn f32(&mut self) -> io::Result<f32>
+    {
+        Ok(f32::from_le_bytes(self.take()?))
+    }
+
+    pub fn vec3(&mut self) -> io::Result<vec3f::Vec3f32>
+    {
+        Ok(vec3f::Vec3f32::new_from_points(self.f32()?, self.f32()?, self.f32()?))
+    }
+}
+
+// 64 bit FNV-1a parameters
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+// Hash of everything a checkpoint's contents depend on, so that a render is
+// only resumed into the same scene with the same settings. FNV-1a over the
+// little endian bytes of every value, which unlike the standard library's
+// hasher stays the same from one build of the renderer to the next.
+pub struct Fingerprint
+{
+    hash: u64,
+}
+
+impl Fingerprint
+{
+    pub fn new() -> Fingerprint
+    {
+        Fingerprint { hash: FNV_OFFSET_BASIS }
+    }
+
+    pub fn u32(&mut self, x: u32)
+    {
+        for byte in x.to_le_bytes()
+        {
+            self.hash ^= byte as u64;
+            self.hash = self.hash.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    pub fn f32(&mut self, x: f32)
+    {
+        self.u32(x.to_bits());
+    }
+
+    pub fn vec3(&mut self, v: &vec3f::Vec3f32)
+    {
+        self.f32(v.x);
+        self.f32(v.y);
+        self.f32(v.z);
+    }
+
+    pub fn finish(&self) -> u64
+    {
+        self.hash
+    }
+}
+
+// Where the state of the render producing `image_path` is kept
+pub fn path(image_path: &str) -> String
+{
+    let stem = image_path.strip_suffix(".ppm").unwrap_or(image_path);
+    format!("{}.checkpoint", stem)
+}
+
+// Writes next to the old checkpoint first and then replaces it, so that
+// dying half way through leaves the previous one intact
+pub fn save(path: &str, encoder: Encoder) -> io::Result<()>
+{
+    let partial = format!("{}.partial", path);
+    fs::write(&partial, &encoder.bytes)?;
+    fs::rename(&partial, path)
+}
+
+pub fn load(path: &str) -> io::Result<Decoder>
+{
+    let bytes = fs::read(path)?;
+    if !bytes.starts_with(MAGIC)
+    {
+        return Err(invalid("not a checkpoint"));
+    }
+    Ok(Decoder { bytes, position: MAGIC.len() })
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::aov;
+    use crate::bokeh;
+    use crate::camera;
+    use crate::film;
+    use crate::hittable;
+
+    fn components(values: &[vec3f::Vec3f32]) -> Vec<[f32; 3]>
+    {
+        values.iter().map(|v| [v.x, v.y, v.z]).collect()
+    }
+
+    #[test]
+    fn film_and_aovs_survive_a_round_trip()
+    {
+        let colour = |x: f32| vec3f::Vec3f32::new_from_points(x, 2.0 * x, 0.5);
+        let mut film = film::Film::new(3, 2, film::PixelFilter::tent());
+        film.add_sample(0.3, 0.7, colour(1.0));
+        film.add_sample(2.1, 1.4, colour(3.0));
+        let aovs = [aov::Aov::Depth, aov::Aov::ObjectId, aov::Aov::SampleCount];
+        let mut buffers = aov::AovBuffers::new(3, 2, &aovs);
+        let mut sample = aov::SampleAovs::new();
+        sample.depth = 4.5;
+        sample.object_id = 2;
+        buffers.add_sample(1, 0, &sample, colour(1.0), colour(2.0));
+
+        let mut encoder = Encoder::new();
+        encoder.u64(42);
+        film.encode(&mut encoder);
+        buffers.encode(&mut encoder);
+        let path = std::env::temp_dir().join(format!("checkpoint_test_{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        save(path, encoder).unwrap();
+        let mut decoder = load(path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        let mut decoded_film = film::Film::new(3, 2, film::PixelFilter::tent());
+        let mut decoded_buffers = aov::AovBuffers::new(3, 2, &aovs);
+        assert_eq!(decoder.u64().unwrap(), 42);
+        decoded_film.decode(&mut decoder).unwrap();
+        decoded_buffers.decode(&mut decoder).unwrap();
+        assert!(decoder.u32().is_err());
+
+        assert_eq!(components(&decoded_film.pixels()), components(&film.pixels()));
+        for aov in aovs
+        {
+            assert_eq!(components(decoded_buffers.pass(aov).unwrap()),
+                       components(buffers.pass(aov).unwrap()));
+        }
+    }
+
+    #[test]
+    fn fingerprint_follows_materials_and_cameras()
+    {
+        let hash = |surface: &hittable::Surface, camera: &camera::CameraDescription|
+        {
+            let mut fingerprint = Fingerprint::new();
+            surface.fingerprint(&mut fingerprint);
+            camera.fingerprint(&mut fingerprint);
+            fingerprint.finish()
+        };
+        let surface = hittable::Surface
+        {
+            material: hittable::Material::Metal,
+            albedo: vec3f::Vec3f32::new_from_points(0.7, 0.6, 0.5),
+            fuzz: 0.1,
+            refraction: 0.0,
+        };
+        let camera = camera::CameraDescription
+        {
+            projection: camera::Projection::Perspective,
+            look_from: vec3f::Vec3f32::new_from_points(13.0, 2.0, 3.0),
+            look_at: vec3f::Vec3f32::zeroes(),
+            vup: vec3f::Vec3f32::new_from_points(0.0, 1.0, 0.0),
+            fov: 20.0,
+            aspect: 2.0,
+            aperature: 0.1,
+            focus_dist: 10.0,
+            lens: bokeh::LensEffects::none(),
+            shutter: camera::Shutter::instant(),
+        };
+        let original = hash(&surface, &camera);
+        assert_eq!(hash(&surface, &camera.clone()), original);
+
+        let rougher = hittable::Surface { fuzz: 0.2, ..surface };
+        assert_ne!(hash(&rougher, &camera), original);
+        let glass = hittable::Surface { material: hittable::Material::Dielectric, ..surface };
+        assert_ne!(hash(&glass, &camera), original);
+
+        let wider = camera::CameraDescription { fov: 30.0, ..camera.clone() };
+        assert_ne!(hash(&surface, &wider), original);
+        let refocused = camera::CameraDescription { focus_dist: 12.0, ..camera.clone() };
+        assert_ne!(hash(&surface, &refocused), original);
+    }
+}
@@ -0,0 +1,60 @@
+use crate::vec3f;
+
+// A rectangle of pixels, x from the left and y from the top of the image,
+// rendered on its own while the camera still frames the whole image
+#[derive(Clone)]
+pub struct CropWindow
+{
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    // Image of the full frame to paste the rectangle into, instead of
+    // writing the rectangle out on its own
+    pub composite: Option<String>,
+}
+
+impl CropWindow
+{
+    pub fn fits(&self, nx: u32, ny: u32) -> bool
+    {
+        self.width > 0 && self.height > 0
+            && self.x + self.width <= nx && self.y + self.height <= ny
+    }
+
+    // Whether pixel (i, j) of an image ny pixels high is inside, or within
+    // `margin` pixels of it, with j counting rows up from the bottom like
+    // the film does
+    pub fn contains(&self, i: u32, j: u32, ny: u32, margin: u32) -> bool
+    {
+        let row = ny - 1 - j;
+        i + margin >= self.x && i < self.x + self.width + margin
+            && row + margin >= self.y && row < self.y + self.height + margin
+    }
+
+    // The values inside the window out of an image nx pixels wide, both in
+    // rows from the top down
+    pub fn extract<T: Copy>(&self, values: &[T], nx: u32) -> Vec<T>
+    {
+        values.chunks(nx as usize)
+            .skip(self.y as usize)
+            .take(self.height as usize)
+            .flat_map(|row| row[self.x as usize .. (self.x + self.width) as usize].iter().copied())
+            .collect()
+    }
+
+    // Pastes windows stacked one above the other, as rendered for each eye
+    // of a stereo pair, into a frame holding the full views stacked the same
+    // way, each ny pixels high
+    pub fn composite(&self, frame: &mut [vec3f::Vec3f32], nx: u32, ny: u32,
+                     windows: &[vec3f::Vec3f32])
+    {
+        for (k, row) in windows.chunks(self.width as usize).enumerate()
+        {
+            let view = k as u32 / self.height;
+            let frame_row = view * ny + self.y + k as u32 % self.height;
+            let start = (frame_row * nx + self.x) as usize;
+            frame[start .. start + self.width as usize].copy_from_slice(row);
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use crate::vec3f;
+
+// Edge stopping widths: the larger one is, the more different two pixels can
+// be in that feature and still be blended
+#[derive(Copy, Clone)]
+pub struct EdgeStops
+{
+    pub colour: f32,
+    pub normal: f32,
+    pub albedo: f32,
+}
+
+#[derive(Copy, Clone)]
+pub enum Denoiser
+{
+    // Dammertz et al.'s edge-avoiding à-trous wavelet filter: a 5x5 B3
+    // spline kernel whose taps spread twice as far apart every iteration,
+    // with the colour stop halved each time as the noise goes down
+    Atrous
+    {
+        iterations: u32,
+        stops: EdgeStops,
+    },
+    // Single pass cross bilateral filter over a square window
+    JointBilateral
+    {
+        radius: u32,
+        // Standard deviation of the spatial Gaussian in pixels
+        sigma: f32,
+        stops: EdgeStops,
+    },
+}
+
+// Guide images the denoiser stops its blur at the edges of, in the same row
+// order as the pixels
+pub struct Features<'a>
+{
+    pub albedo: &'a [vec3f::Vec3f32],
+    pub normal: &'a [vec3f::Vec3f32],
+}
+
+const B3_SPLINE: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+fn edge_weight(stops: &EdgeStops, features: &Features,
+               colours: &[vec3f::Vec3f32], p: usize, q: usize) -> f32
+{
+    let colour = (colours[p] - colours[q]).squared_length() / (stops.colour * stops.colour);
+    let normal = (features.normal[p] - features.normal[q]).squared_length()
+        / (stops.normal * stops.normal);
+    let albedo = (features.albedo[p] - features.albedo[q]).squared_length()
+        / (stops.albedo * stops.albedo);
+    (-(colour + normal + albedo)).exp()
+}
+
+impl Denoiser
+{
+    pub fn atrous() -> Denoiser
+    {
+        Denoiser::Atrous
+        {
+            iterations: 5,
+            stops: EdgeStops { colour: 0.5, normal: 0.3, albedo: 0.1 },
+        }
+    }
+
+    pub fn joint_bilateral() -> Denoiser
+    {
+        Denoiser::JointBilateral
+        {
+            radius: 6,
+            sigma: 3.0,
+            stops: EdgeStops { colour: 0.5, normal: 0.3, albedo: 0.1 },
+        }
+    }
+
+    pub fn apply(&self, pixels: &[vec3f::Vec3f32], features: &Features,
+                 nx: u32, ny: u32) -> Vec<vec3f::Vec3f32>
+    {
+        match *self
+        {
+            Denoiser::Atrous { iterations, ref stops } =>
+            {
+                let mut current = pixels.to_vec();
+                for iteration in 0 .. iterations
+                {
+                    let step = 1 << iteration;
+                    let pass_stops = EdgeStops
+                    {
+                        colour: stops.colour / (1 << iteration) as f32,
+                        ..*stops
+                    };
+                    current = filter(&current, features, nx, ny, 2, |di, dj|
+                    {
+                        let spline = B3_SPLINE[(di + 2) as usize] * B3_SPLINE[(dj + 2) as usize];
+                        (di * step, dj * step, spline)
+                    }, &pass_stops);
+                }
+                current
+            },
+
+            Denoiser::JointBilateral { radius, sigma, ref stops } =>
+            {
+                filter(pixels, features, nx, ny, radius as i32, |di, dj|
+                {
+                    let spatial = (-((di * di + dj * dj) as f32) / (2.0 * sigma * sigma)).exp();
+                    (di, dj, spatial)
+                }, stops)
+            },
+        }
+    }
+}
+
+// One pass of a cross bilateral filter. `tap` turns a kernel index in
+// [-half_width, half_width] on each axis into a pixel offset and a spatial
+// weight; taps falling outside the image are skipped.
+fn filter(colours: &[vec3f::Vec3f32], features: &Features, nx: u32, ny: u32,
+          half_width: i32, tap: impl Fn(i32, i32) -> (i32, i32, f32),
+          stops: &EdgeStops) -> Vec<vec3f::Vec3f32>
+{
+    let mut filtered = Vec::with_capacity(colours.len());
+    for y in 0 .. ny as i32
+    {
+        for x in 0 .. nx as i32
+        {
+            let p = (y * nx as i32 + x) as usize;
+            let mut sum = vec3f::Vec3f32::zeroes();
+            let mut total = 0.0;
+            for dj in -half_width ..= half_width
+            {
+                for di in -half_width ..= half_width
+                {
+                    let (ox, oy, spatial) = tap(di, dj);
+                    let (qx, qy) = (x + ox, y + oy);
+                    if qx < 0 || qy < 0 || qx >= nx as i32 || qy >= ny as i32
+                    {
+                        continue;
+                    }
+                    let q = (qy * nx as i32 + qx) as usize;
+                    let weight = spatial * edge_weight(stops, features, colours, p, q);
+                    sum += colours[q] * weight;
+                    total += weight;
+                }
+            }
+            // The centre tap always has a positive weight
+            filtered.push(sum / total);
+        }
+    }
+    filtered
+}
@@ -0,0 +1,250 @@
+use std::io;
+
+use crate::vec3f;
+use crate::checkpoint;
+
+#[derive(Copy, Clone)]
+pub enum FilterShape
+{
+    Box,
+    Tent,
+    // exp(-alpha x^2), shifted down so it reaches zero at the radius
+    Gaussian
+    {
+        alpha: f32,
+    },
+    // Mitchell–Netravali cubic, B = C = 1/3 is the usual choice
+    Mitchell
+    {
+        b: f32,
+        c: f32,
+    },
+    // Sinc windowed by a wider sinc, with as many lobes as the radius
+    Lanczos,
+}
+
+// A separable reconstruction filter reaching `radius` pixels out from the
+// sample in x and y
+#[derive(Copy, Clone)]
+pub struct PixelFilter
+{
+    pub shape: FilterShape,
+    pub radius: f32,
+}
+
+fn sinc(x: f32) -> f32
+{
+    if x.abs() < 1e-5
+    {
+        return 1.0;
+    }
+    let px = std::f32::consts::PI * x;
+    px.sin() / px
+}
+
+impl PixelFilter
+{
+    // A box exactly one pixel wide, so every sample only counts towards the
+    // pixel it was taken in
+    pub fn pixel_box() -> PixelFilter
+    {
+        PixelFilter
+        {
+            shape: FilterShape::Box,
+            radius: 0.5,
+        }
+    }
+
+    pub fn tent() -> PixelFilter
+    {
+        PixelFilter
+        {
+            shape: FilterShape::Tent,
+            radius: 1.0,
+        }
+    }
+
+    pub fn gaussian() -> PixelFilter
+    {
+        PixelFilter
+        {
+            shape: FilterShape::Gaussian { alpha: 2.0 },
+            radius: 1.5,
+        }
+    }
+
+    pub fn mitchell() -> PixelFilter
+    {
+        PixelFilter
+        {
+            shape: FilterShape::Mitchell { b: 1.0 / 3.0, c: 1.0 / 3.0 },
+            radius: 2.0,
+        }
+    }
+
+    // Three lobes either side
+    pub fn lanczos() -> PixelFilter
+    {
+        PixelFilter
+        {
+            shape: FilterShape::Lanczos,
+            radius: 3.0,
+        }
+    }
+
+    fn evaluate_1d(&self, x: f32) -> f32
+    {
+        let x = x.abs();
+        if x > self.radius
+        {
+            return 0.0;
+        }
+        match self.shape
+        {
+            FilterShape::Box => 1.0,
+            FilterShape::Tent => self.radius - x,
+            FilterShape::Gaussian { alpha } =>
+                ((-alpha * x * x).exp() - (-alpha * self.radius * self.radius).exp()).max(0.0),
+            FilterShape::Mitchell { b, c } =>
+            {
+                // The cubic is defined over [0, 2]
+                let t = 2.0 * x / self.radius;
+                let t2 = t * t;
+                let t3 = t2 * t;
+                if t < 1.0
+                {
+                    ((12.0 - 9.0 * b - 6.0 * c) * t3 + (-18.0 + 12.0 * b + 6.0 * c) * t2
+                     + (6.0 - 2.0 * b)) / 6.0
+                }
+                else
+                {
+                    ((-b - 6.0 * c) * t3 + (6.0 * b + 30.0 * c) * t2
+                     + (-12.0 * b - 48.0 * c) * t + (8.0 * b + 24.0 * c)) / 6.0
+                }
+            },
+            FilterShape::Lanczos => sinc(x) * sinc(x / self.radius),
+        }
+    }
+
+    pub fn evaluate(&self, dx: f32, dy: f32) -> f32
+    {
+        self.evaluate_1d(dx) * self.evaluate_1d(dy)
+    }
+
+    pub fn fingerprint(&self, fingerprint: &mut checkpoint::Fingerprint)
+    {
+        match self.shape
+        {
+            FilterShape::Box => fingerprint.u32(0),
+            FilterShape::Tent => fingerprint.u32(1),
+            FilterShape::Gaussian { alpha } =>
+            {
+                fingerprint.u32(2);
+                fingerprint.f32(alpha);
+            },
+            FilterShape::Mitchell { b, c } =>
+            {
+                fingerprint.u32(3);
+                fingerprint.f32(b);
+                fingerprint.f32(c);
+            },
+            FilterShape::Lanczos => fingerprint.u32(4),
+        }
+        fingerprint.f32(self.radius);
+    }
+}
+
+// Accumulates filtered samples. Sample positions are continuous pixel
+// coordinates with y measured up from the bottom of the image, so pixel
+// (i, j) covers [i, i + 1] x [j, j + 1].
+pub struct Film
+{
+    nx: u32,
+    ny: u32,
+    filter: PixelFilter,
+    sums: Vec<vec3f::Vec3f32>,
+    weights: Vec<f32>,
+}
+
+impl Film
+{
+    pub fn new(nx: u32, ny: u32, filter: PixelFilter) -> Film
+    {
+        Film
+        {
+            nx,
+            ny,
+            filter,
+            sums: vec![vec3f::Vec3f32::zeroes(); (nx * ny) as usize],
+            weights: vec![0.0; (nx * ny) as usize],
+        }
+    }
+
+    // Splats a sample onto every pixel whose centre lies within the filter
+    // radius
+    pub fn add_sample(&mut self, x: f32, y: f32, colour: vec3f::Vec3f32)
+    {
+        let radius = self.filter.radius;
+        let i0 = (x - 0.5 - radius).ceil().max(0.0) as u32;
+        let j0 = (y - 0.5 - radius).ceil().max(0.0) as u32;
+        let i1 = ((x - 0.5 + radius).floor() as i64).min(self.nx as i64 - 1);
+        let j1 = ((y - 0.5 + radius).floor() as i64).min(self.ny as i64 - 1);
+        for j in j0 as i64 ..= j1
+        {
+            for i in i0 as i64 ..= i1
+            {
+                let weight = self.filter.evaluate(i as f32 + 0.5 - x, j as f32 + 0.5 - y);
+                if weight == 0.0
+                {
+                    continue;
+                }
+                let index = (j as u32 * self.nx + i as u32) as usize;
+                self.sums[index] += colour * weight;
+                self.weights[index] += weight;
+            }
+        }
+    }
+
+    pub fn encode(&self, encoder: &mut checkpoint::Encoder)
+    {
+        for (sum, weight) in self.sums.iter().zip(&self.weights)
+        {
+            encoder.vec3(sum);
+            encoder.f32(*weight);
+        }
+    }
+
+    pub fn decode(&mut self, decoder: &mut checkpoint::Decoder) -> io::Result<()>
+    {
+        for (sum, weight) in self.sums.iter_mut().zip(&mut self.weights)
+        {
+            *sum = decoder.vec3()?;
+            *weight = decoder.f32()?;
+        }
+        Ok(())
+    }
+
+    // Filtered pixel values in rows from the top of the image down. Pixels
+    // whose weights cancel out, which can happen with the negative lobes of
+    // Mitchell and Lanczos, are left black.
+    pub fn pixels(&self) -> Vec<vec3f::Vec3f32>
+    {
+        let mut pixels = Vec::with_capacity((self.nx * self.ny) as usize);
+        for j in (0 .. self.ny).rev()
+        {
+            for i in 0 .. self.nx
+            {
+                let index = (j * self.nx + i) as usize;
+                if self.weights[index] > 0.0
+                {
+                    pixels.push(self.sums[index] / self.weights[index]);
+                }
+                else
+                {
+                    pixels.push(vec3f::Vec3f32::zeroes());
+                }
+            }
+        }
+        pixels
+    }
+}
@@ -0,0 +1,297 @@
+use std::io;
+
+use crate::vec3f;
+use crate::pgm;
+use crate::ray;
+use crate::checkpoint;
+use crate::hittable::{HitRecord, Hittable, Surface};
+
+// A regular grid of nx by nz height samples in [0, 1]. The grid spans
+// `size.x` by `size.z` starting at `origin`, and a sample of 1.0 sits
+// `size.y` above the origin.
+pub struct Heightfield
+{
+    nx: usize,
+    nz: usize,
+    heights: Vec<f32>,
+    normals: Vec<vec3f::Vec3f32>,
+    // Lowest and highest world height of the four corners of every cell,
+    // used to skip cells the ray passes over or under
+    cell_bounds: Vec<(f32, f32)>,
+    origin: vec3f::Vec3f32,
+    size: vec3f::Vec3f32,
+    min_height: f32,
+    max_height: f32,
+    surface: Surface,
+}
+
+// Möller–Trumbore ray/triangle test, returning the ray parameter and the
+// barycentric weights of v1 and v2
+fn hit_triangle(origin: &vec3f::Vec3f32, direction: &vec3f::Vec3f32,
+                v0: &vec3f::Vec3f32, v1: &vec3f::Vec3f32,
+                v2: &vec3f::Vec3f32) -> Option<(f32, f32, f32)>
+{
+    let edge1 = *v1 - *v0;
+    let edge2 = *v2 - *v0;
+    let pvec = direction.cross_product(&edge2);
+    let det = edge1.dot_product(&pvec);
+    if det.abs() < 1e-12
+    {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+    let tvec = *origin - *v0;
+    let u = tvec.dot_product(&pvec) * inv_det;
+    if !(0.0 ..= 1.0).contains(&u)
+    {
+        return None;
+    }
+    let qvec = tvec.cross_product(&edge1);
+    let v = direction.dot_product(&qvec) * inv_det;
+    if v < 0.0 || u + v > 1.0
+    {
+        return None;
+    }
+    Some((edge2.dot_product(&qvec) * inv_det, u, v))
+}
+
+impl Heightfield
+{
+    pub fn new(nx: usize, nz: usize, heights: Vec<f32>,
+               origin: vec3f::Vec3f32, size: vec3f::Vec3f32,
+               surface: Surface) -> Heightfield
+    {
+        assert!(nx >= 2 && nz >= 2, "a heightfield needs at least 2x2 samples");
+        assert_eq!(heights.len(), nx * nz);
+
+        let mut field = Heightfield
+        {
+            nx,
+            nz,
+            heights,
+            normals: Vec::with_capacity(nx * nz),
+            cell_bounds: Vec::with_capacity((nx - 1) * (nz - 1)),
+            origin,
+            size,
+            min_height: f32::MAX,
+            max_height: -f32::MAX,
+            surface,
+        };
+
+        for j in 0 .. nz
+        {
+            for i in 0 .. nx
+            {
+                let normal = field.vertex_normal(i, j);
+                field.normals.push(normal);
+                let h = field.height(i, j);
+                field.min_height = field.min_height.min(h);
+                field.max_height = field.max_height.max(h);
+            }
+        }
+
+        for j in 0 .. nz - 1
+        {
+            for i in 0 .. nx - 1
+            {
+                let corners = [field.height(i, j), field.height(i + 1, j),
+                               field.height(i, j + 1), field.height(i + 1, j + 1)];
+                let low = corners.iter().cloned().fold(f32::MAX, f32::min);
+                let high = corners.iter().cloned().fold(-f32::MAX, f32::max);
+                field.cell_bounds.push((low, high));
+            }
+        }
+        field
+    }
+
+    // Loads the heights from a grayscale pgm image, with rows of the image
+    // running along +z
+    pub fn from_pgm(path: &str, origin: vec3f::Vec3f32,
+                    size: vec3f::Vec3f32,
+                    surface: Surface) -> io::Result<Heightfield>
+    {
+        let (width, height, heights) = pgm::read(path)?;
+        if width < 2 || height < 2
+        {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      "a heightfield needs at least 2x2 samples"));
+        }
+        Ok(Heightfield::new(width, height, heights, origin, size, surface))
+    }
+
+    fn cell_width(&self) -> f32
+    {
+        self.size.x / (self.nx - 1) as f32
+    }
+
+    fn cell_depth(&self) -> f32
+    {
+        self.size.z / (self.nz - 1) as f32
+    }
+
+    fn height(&self, i: usize, j: usize) -> f32
+    {
+        self.origin.y + self.heights[j * self.nx + i] * self.size.y
+    }
+
+    fn vertex(&self, i: usize, j: usize) -> vec3f::Vec3f32
+    {
+        vec3f::Vec3f32::new_from_points(self.origin.x + i as f32 * self.cell_width(),
+                                        self.height(i, j),
+                                        self.origin.z + j as f32 * self.cell_depth())
+    }
+
+    // Central differences of the neighbouring samples, falling back to
+    // one sided differences along the border
+    fn vertex_normal(&self, i: usize, j: usize) -> vec3f::Vec3f32
+    {
+        let i0 = i.saturating_sub(1);
+        let i1 = (i + 1).min(self.nx - 1);
+        let j0 = j.saturating_sub(1);
+        let j1 = (j + 1).min(self.nz - 1);
+        let dhdx = (self.height(i1, j) - self.height(i0, j)) /
+            ((i1 - i0) as f32 * self.cell_width());
+        let dhdz = (self.height(i, j1) - self.height(i, j0)) /
+            ((j1 - j0) as f32 * self.cell_depth());
+        vec3f::Vec3f32::new_from_points(-dhdx, 1.0, -dhdz).unit_vector()
+    }
+
+    // Tests the two triangles of cell (i, j), filling `rec` with the
+    // closest hit and a normal interpolated from the corner normals
+    fn hit_cell(&self, i: usize, j: usize, r: &mut ray::Ray,
+                tmin: f32, tmax: f32, rec: &mut HitRecord) -> bool
+    {
+        let origin = r.origin();
+        let direction = r.direction();
+        let corners = [(i, j), (i + 1, j), (i + 1, j + 1), (i, j + 1)];
+        let triangles = [[corners[0], corners[1], corners[2]],
+                         [corners[0], corners[2], corners[3]]];
+        let mut closest_so_far = tmax;
+        let mut hit_anything = false;
+        for triangle in &triangles
+        {
+            let v0 = self.vertex(triangle[0].0, triangle[0].1);
+            let v1 = self.vertex(triangle[1].0, triangle[1].1);
+            let v2 = self.vertex(triangle[2].0, triangle[2].1);
+            if let Some((t, u, v)) = hit_triangle(&origin, &direction, &v0, &v1, &v2)
+            {
+                if t < closest_so_far && t > tmin
+                {
+                    let n0 = self.normals[triangle[0].1 * self.nx + triangle[0].0];
+                    let n1 = self.normals[triangle[1].1 * self.nx + triangle[1].0];
+                    let n2 = self.normals[triangle[2].1 * self.nx + triangle[2].0];
+                    closest_so_far = t;
+                    hit_anything = true;
+                    rec.t = t;
+                    rec.p = r.point_at_parameter(&t);
+                    rec.normal = (n0 * (1.0 - u - v) + n1 * u + n2 * v).unit_vector();
+                }
+            }
+        }
+        hit_anything
+    }
+}
+
+impl Hittable for Heightfield
+{
+    fn hit(&self, r: &mut ray::Ray, tmin: f32, tmax: f32,
+           rec: &mut HitRecord) -> bool
+    {
+        let origin = r.origin();
+        let direction = r.direction();
+
+        // Clip the ray against the bounding box of the terrain
+        let low = vec3f::Vec3f32::new_from_points(self.origin.x, self.min_height,
+                                                  self.origin.z);
+        let high = vec3f::Vec3f32::new_from_points(self.origin.x + self.size.x,
+                                                   self.max_height,
+                                                   self.origin.z + self.size.z);
+        let (t_enter, t_exit) = match r.box_span(&low, &high, tmin, tmax)
+        {
+            Some(span) => span,
+            None => return false,
+        };
+
+        // Walk the cells under the ray front to back with a 2D DDA, so the
+        // first cell holding a hit holds the closest one
+        let dx = self.cell_width();
+        let dz = self.cell_depth();
+        let start = origin + direction * t_enter;
+        let last_i = self.nx as isize - 2;
+        let last_j = self.nz as isize - 2;
+        let mut i = (((start.x - self.origin.x) / dx).floor() as isize).clamp(0, last_i);
+        let mut j = (((start.z - self.origin.z) / dz).floor() as isize).clamp(0, last_j);
+
+        let step_i: isize = if direction.x > 0.0 { 1 } else { -1 };
+        let step_j: isize = if direction.z > 0.0 { 1 } else { -1 };
+        let (mut t_next_i, t_delta_i) = if direction.x != 0.0
+        {
+            let boundary = self.origin.x +
+                (i + if step_i > 0 { 1 } else { 0 }) as f32 * dx;
+            ((boundary - origin.x) / direction.x, dx / direction.x.abs())
+        }
+        else
+        {
+            (f32::MAX, f32::MAX)
+        };
+        let (mut t_next_j, t_delta_j) = if direction.z != 0.0
+        {
+            let boundary = self.origin.z +
+                (j + if step_j > 0 { 1 } else { 0 }) as f32 * dz;
+            ((boundary - origin.z) / direction.z, dz / direction.z.abs())
+        }
+        else
+        {
+            (f32::MAX, f32::MAX)
+        };
+
+        let mut t_cell = t_enter;
+        loop
+        {
+            let t_cell_exit = t_next_i.min(t_next_j).min(t_exit);
+            let y0 = origin.y + direction.y * t_cell;
+            let y1 = origin.y + direction.y * t_cell_exit;
+            let (low, high) = self.cell_bounds[j as usize * (self.nx - 1) + i as usize];
+            if y0.max(y1) >= low && y0.min(y1) <= high &&
+                self.hit_cell(i as usize, j as usize, r, tmin, tmax, rec)
+            {
+                return true;
+            }
+
+            if t_next_i < t_next_j
+            {
+                i += step_i;
+                t_cell = t_next_i;
+                t_next_i += t_delta_i;
+            }
+            else
+            {
+                j += step_j;
+                t_cell = t_next_j;
+                t_next_j += t_delta_j;
+            }
+            if i < 0 || i > last_i || j < 0 || j > last_j || t_cell > t_exit
+            {
+                return false;
+            }
+        }
+    }
+
+    fn surface(&self) -> &Surface
+    {
+        &self.surface
+    }
+
+    fn fingerprint(&self, fingerprint: &mut checkpoint::Fingerprint)
+    {
+        fingerprint.u32(self.nx as u32);
+        fingerprint.u32(self.nz as u32);
+        for &height in &self.heights
+        {
+            fingerprint.f32(height);
+        }
+        fingerprint.vec3(&self.origin);
+        fingerprint.vec3(&self.size);
+        self.surface.fingerprint(fingerprint);
+    }
+}
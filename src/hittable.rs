@@ -0,0 +1,132 @@
+use crate::vec3f;
+use crate::ray;
+use crate::volume;
+use crate::optics;
+use crate::principled;
+use crate::layered;
+use crate::subsurface;
+use crate::checkpoint;
+
+#[derive(Copy, Clone)]
+pub enum Material
+{
+    Lambertian,
+    // Rough GGX mirror whose reflectance at normal incidence is the albedo
+    Metal,
+    // Smooth glass, absorbing light inside it so that the albedo is left
+    // after one unit of distance
+    Dielectric,
+    // Scattering point inside a participating medium
+    Medium(volume::PhaseFunction),
+    // Rough GGX mirror reflecting as the metal with this complex refractive
+    // index, tinted by the albedo
+    Conductor(optics::ComplexIor),
+    // Glass with a GGX microfacet surface, frosted at higher roughness,
+    // absorbing light inside it as the smooth dielectric does
+    RoughDielectric,
+    // Disney's principled BSDF, with the albedo as the base colour and fuzz
+    // as the roughness
+    Principled(principled::Principled),
+    // Clear coat over a diffuse or metallic substrate, coloured by the
+    // albedo and as rough as the fuzz
+    Coated(layered::Coating),
+    // Translucent closed object scattering light beneath its surface, with
+    // a boundary as rough as the fuzz
+    Subsurface(subsurface::Subsurface),
+}
+
+impl Material
+{
+    // Number identifying the kind of material in the material id pass,
+    // counting from one so that zero is left for the sky
+    pub fn id(&self) -> u32
+    {
+        match *self
+        {
+            Material::Lambertian => 1,
+            Material::Metal => 2,
+            Material::Dielectric => 3,
+            Material::Medium(_) => 4,
+            Material::Conductor(_) => 5,
+            Material::RoughDielectric => 6,
+            Material::Principled(_) => 7,
+            Material::Coated(_) => 8,
+            Material::Subsurface(_) => 9,
+        }
+    }
+
+    pub fn fingerprint(&self, fingerprint: &mut checkpoint::Fingerprint)
+    {
+        fingerprint.u32(self.id());
+        match *self
+        {
+            Material::Medium(ref phase) => phase.fingerprint(fingerprint),
+            Material::Conductor(ref ior) => ior.fingerprint(fingerprint),
+            Material::Principled(ref principled) => principled.fingerprint(fingerprint),
+            Material::Coated(ref coating) => coating.fingerprint(fingerprint),
+            Material::Subsurface(ref subsurface) => subsurface.fingerprint(fingerprint),
+            Material::Lambertian | Material::Metal | Material::Dielectric
+                | Material::RoughDielectric => (),
+        }
+    }
+}
+
+// How a surface scatters light, shared by every primitive so that `color`
+// does not need to know which kind of object it hit
+#[derive(Copy, Clone)]
+pub struct Surface
+{
+    pub material: Material,
+    pub albedo: vec3f::Vec3f32,
+    // Roughness of metals, conductors, rough dielectrics, principled
+    // surfaces, coated substrates and subsurface boundaries, from 0 for a
+    // mirror to 1
+    pub fuzz: f32,
+    pub refraction: f32,
+}
+
+impl Surface
+{
+    pub fn fingerprint(&self, fingerprint: &mut checkpoint::Fingerprint)
+    {
+        self.material.fingerprint(fingerprint);
+        fingerprint.vec3(&self.albedo);
+        fingerprint.f32(self.fuzz);
+        fingerprint.f32(self.refraction);
+    }
+}
+
+pub struct HitRecord
+{
+    pub t: f32,
+    pub p: vec3f::Vec3f32,
+    pub normal: vec3f::Vec3f32,
+    // Radiance given off at the hit point, only set by emissive media
+    pub emitted: vec3f::Vec3f32,
+}
+
+impl HitRecord
+{
+    pub fn zeroes() -> HitRecord
+    {
+        HitRecord
+        {
+            t: 0.0f32,
+            p: vec3f::Vec3f32::zeroes(),
+            normal: vec3f::Vec3f32::zeroes(),
+            emitted: vec3f::Vec3f32::zeroes(),
+        }
+    }
+}
+
+pub trait Hittable
+{
+    fn hit(&self, r: &mut ray::Ray, tmin: f32, tmax: f32,
+           rec: &mut HitRecord) -> bool;
+
+    fn surface(&self) -> &Surface;
+
+    // Hashes everything about the object that changes what it renders, so
+    // that a checkpoint is only resumed into the same scene
+    fn fingerprint(&self, fingerprint: &mut checkpoint::Fingerprint);
+}
@@ -0,0 +1,136 @@
+use crate::vec3f;
+use crate::optics;
+use crate::microfacet;
+use crate::rng;
+use crate::checkpoint;
+
+// Bounces between the substrate and the underside of the coat before a walk
+// gives up, by when hardly any light is left in it
+const MAX_BOUNCES: u32 = 16;
+
+// What the coat is laid over, coloured by the albedo of the surface and as
+// rough as its fuzz
+#[derive(Copy, Clone)]
+pub enum Substrate
+{
+    Diffuse,
+    // GGX mirror reflecting the albedo at normal incidence
+    Metal,
+}
+
+// A clear dielectric layer over a substrate, with the refractive index of
+// the surface. Light is followed through the layers one bounce at a time, so
+// reflections trapped inside the coat darken and saturate the substrate the
+// way they do on real varnish.
+#[derive(Copy, Clone)]
+pub struct Coating
+{
+    pub substrate: Substrate,
+    // Roughness of the top of the coat, apart from that of the substrate
+    pub roughness: f32,
+    // Colour left after crossing the coat straight through once
+    pub tint: vec3f::Vec3f32,
+}
+
+impl Coating
+{
+    pub fn plastic() -> Coating
+    {
+        Coating
+        {
+            substrate: Substrate::Diffuse,
+            roughness: 0.05,
+            tint: vec3f::Vec3f32::new_from_points(1.0, 1.0, 1.0),
+        }
+    }
+
+    // Mirror-like lacquer over metal flakes
+    pub fn car_paint() -> Coating
+    {
+        Coating
+        {
+            substrate: Substrate::Metal,
+            roughness: 0.02,
+            tint: vec3f::Vec3f32::new_from_points(1.0, 1.0, 1.0),
+        }
+    }
+
+    // Amber, slightly rough finish over wood
+    pub fn varnish() -> Coating
+    {
+        Coating
+        {
+            substrate: Substrate::Diffuse,
+            roughness: 0.15,
+            tint: vec3f::Vec3f32::new_from_points(0.95, 0.8, 0.55),
+        }
+    }
+
+    // Scatters light arriving at the substrate from `wo`, both in the local
+    // frame of the surface above it
+    fn scatter_substrate(&self, wo: &vec3f::Vec3f32, albedo: &vec3f::Vec3f32, roughness: f32)
+                         -> Option<(vec3f::Vec3f32, vec3f::Vec3f32)>
+    {
+        match self.substrate
+        {
+            Substrate::Diffuse =>
+                Some((microfacet::sample_cosine_hemisphere(rng::random(), rng::random()), *albedo)),
+            Substrate::Metal =>
+            {
+                let ggx = microfacet::Ggx::from_roughness(roughness);
+                let m = ggx.sample_visible_normal(wo, rng::random(), rng::random());
+                let wi = optics::reflect(&-*wo, &m);
+                if wi.z <= 0.0
+                {
+                    return None;
+                }
+                let fresnel = optics::schlick_tinted(wo.dot_product(&m), albedo);
+                Some((wi, fresnel * (ggx.masking_shadowing(wo, &wi) / ggx.masking(wo))))
+            },
+        }
+    }
+
+    // Follows light from `wo` into the layers until it comes back out,
+    // returning None when it is absorbed or lost
+    pub fn sample(&self, wo: &vec3f::Vec3f32, albedo: &vec3f::Vec3f32, roughness: f32, eta: f32)
+                  -> Option<microfacet::BsdfSample>
+    {
+        let coat = microfacet::RoughDielectric
+        {
+            ggx: microfacet::Ggx::from_roughness(self.roughness),
+            eta,
+        };
+        let top = coat.sample(wo, rng::random(), rng::random(), rng::random())?;
+        let mut weight = top.weight;
+        if top.wi.z > 0.0
+        {
+            return Some(top);
+        }
+
+        let mut down = top.wi;
+        for _ in 0 .. MAX_BOUNCES
+        {
+            weight *= optics::beer_lambert(&self.tint, 1.0 / -down.z);
+            let (up, reflectance) = self.scatter_substrate(&-down, albedo, roughness)?;
+            weight *= reflectance * optics::beer_lambert(&self.tint, 1.0 / up.z);
+
+            // Meets the coat from below, where it either leaves or is
+            // reflected back down to the substrate
+            let interface = coat.sample(&-up, rng::random(), rng::random(), rng::random())?;
+            weight *= interface.weight;
+            if interface.wi.z > 0.0
+            {
+                return Some(microfacet::BsdfSample { wi: interface.wi, weight });
+            }
+            down = interface.wi;
+        }
+        None
+    }
+
+    pub fn fingerprint(&self, fingerprint: &mut checkpoint::Fingerprint)
+    {
+        fingerprint.u32(self.substrate as u32);
+        fingerprint.f32(self.roughness);
+        fingerprint.vec3(&self.tint);
+    }
+}
@@ -0,0 +1,450 @@
+use std::fs;
+use std::io;
+
+use crate::vec3f;
+use crate::rng;
+use crate::ray;
+use crate::optics;
+use crate::camera;
+use crate::checkpoint;
+
+// Lens prescriptions are given in millimetres while the scene is modelled
+// in metres
+const MILLIMETRES_PER_UNIT: f32 = 1000.0;
+
+// Number of film radii the exit pupil is precomputed for. Each bound is
+// found from this many film radii within its bin, with a grid of
+// PUPIL_GRID by PUPIL_GRID rays over the rear element traced from each.
+const PUPIL_BINS: usize = 64;
+const PUPIL_FILM_SAMPLES: usize = 16;
+const PUPIL_GRID: usize = 32;
+
+// One spherical refracting surface, or the aperture stop when the radius
+// is zero
+#[derive(Copy, Clone)]
+pub struct LensInterface
+{
+    // Signed radius of curvature, positive when the centre of curvature lies
+    // on the film side of the surface
+    pub radius: f32,
+    // Distance along the axis to the next interface towards the film
+    pub thickness: f32,
+    // Refractive index of the glass between this interface and the next one
+    // towards the film, zero or one for air
+    pub ior: f32,
+    pub aperture_radius: f32,
+}
+
+// A stack of lens interfaces listed from the front (scene side) element to
+// the rear (film side) one, as in published lens prescriptions
+#[derive(Clone)]
+pub struct LensSystem
+{
+    interfaces: Vec<LensInterface>,
+}
+
+fn medium_ior(ior: f32) -> f32
+{
+    if ior == 0.0
+    {
+        1.0
+    }
+    else
+    {
+        ior
+    }
+}
+
+impl LensSystem
+{
+    pub fn new(interfaces: Vec<LensInterface>) -> LensSystem
+    {
+        assert!(!interfaces.is_empty(), "a lens needs at least one interface");
+        LensSystem { interfaces }
+    }
+
+    // Reads a prescription with one interface per line: radius of curvature,
+    // thickness, refractive index and aperture diameter, all in millimetres.
+    // Blank lines and lines starting with '#' are ignored.
+    pub fn from_file(path: &str) -> io::Result<LensSystem>
+    {
+        let text = fs::read_to_string(path)?;
+        let mut interfaces = Vec::new();
+        for line in text.lines()
+        {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#')
+            {
+                continue;
+            }
+            let values: Vec<f32> = line.split_whitespace()
+                .map(|v| v.parse::<f32>())
+                .collect::<Result<_, _>>()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData,
+                                            format!("malformed lens line: {}", line)))?;
+            if values.len() != 4
+            {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                          format!("expected 4 values per lens line: {}", line)));
+            }
+            interfaces.push(LensInterface
+            {
+                radius: values[0],
+                thickness: values[1],
+                ior: values[2],
+                aperture_radius: values[3] / 2.0,
+            });
+        }
+        if interfaces.is_empty()
+        {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      "lens file has no interfaces"));
+        }
+        Ok(LensSystem::new(interfaces))
+    }
+
+    // Axial position of every interface vertex in lens space, where the
+    // film sits at z = 0, the rear vertex at z = film_distance and the
+    // scene lies towards +z
+    fn vertex_positions(&self, film_distance: f32) -> Vec<f32>
+    {
+        let mut positions = vec![0.0; self.interfaces.len()];
+        let mut z = film_distance;
+        for i in (0 .. self.interfaces.len()).rev()
+        {
+            positions[i] = z;
+            if i > 0
+            {
+                z += self.interfaces[i - 1].thickness;
+            }
+        }
+        positions
+    }
+
+    // Refracts a ray through one interface, returning None if it misses the
+    // interface, is blocked by its rim or is totally internally reflected
+    fn trace_interface(interface: &LensInterface, z: f32, origin: &vec3f::Vec3f32,
+                       direction: &vec3f::Vec3f32, ior_before: f32,
+                       ior_after: f32) -> Option<(vec3f::Vec3f32, vec3f::Vec3f32)>
+    {
+        if interface.radius == 0.0
+        {
+            // Aperture stop: a plane that only blocks
+            if direction.z == 0.0
+            {
+                return None;
+            }
+            let t = (z - origin.z) / direction.z;
+            let p = *origin + *direction * t;
+            if t <= 0.0 || p.x * p.x + p.y * p.y > interface.aperture_radius * interface.aperture_radius
+            {
+                return None;
+            }
+            return Some((p, *direction));
+        }
+
+        let centre = vec3f::Vec3f32::new_from_points(0.0, 0.0, z - interface.radius);
+        let oc = *origin - centre;
+        let b = oc.dot_product(direction);
+        let c = oc.dot_product(&oc) - interface.radius * interface.radius;
+        let discriminant = b * b - c;
+        if discriminant < 0.0
+        {
+            return None;
+        }
+        // Pick the root on the cap around the vertex rather than the far
+        // side of the sphere
+        let root = discriminant.sqrt();
+        let use_closer = (direction.z < 0.0) != (interface.radius < 0.0);
+        let t = if use_closer { -b - root } else { -b + root };
+        if t <= 0.0
+        {
+            return None;
+        }
+        let p = *origin + *direction * t;
+        if p.x * p.x + p.y * p.y > interface.aperture_radius * interface.aperture_radius
+        {
+            return None;
+        }
+        let mut normal = (p - centre).unit_vector();
+        if normal.dot_product(direction) > 0.0
+        {
+            normal = -normal;
+        }
+        let refracted = optics::refract(direction, &normal, ior_before / ior_after)?;
+        Some((p, refracted.unit_vector()))
+    }
+
+    // Traces a ray leaving the film through every interface to the front of
+    // the lens
+    fn trace_from_film(&self, film_distance: f32, origin: &vec3f::Vec3f32,
+                       direction: &vec3f::Vec3f32) -> Option<(vec3f::Vec3f32, vec3f::Vec3f32)>
+    {
+        let positions = self.vertex_positions(film_distance);
+        let mut p = *origin;
+        let mut d = direction.unit_vector();
+        for i in (0 .. self.interfaces.len()).rev()
+        {
+            let ior_before = medium_ior(self.interfaces[i].ior);
+            let ior_after = if i > 0 { medium_ior(self.interfaces[i - 1].ior) } else { 1.0 };
+            let (next_p, next_d) = LensSystem::trace_interface(&self.interfaces[i], positions[i],
+                                                               &p, &d, ior_before, ior_after)?;
+            p = next_p;
+            d = next_d;
+        }
+        Some((p, d))
+    }
+
+    // Traces a ray arriving from the scene through every interface to the
+    // back of the lens
+    fn trace_from_scene(&self, film_distance: f32, origin: &vec3f::Vec3f32,
+                        direction: &vec3f::Vec3f32) -> Option<(vec3f::Vec3f32, vec3f::Vec3f32)>
+    {
+        let positions = self.vertex_positions(film_distance);
+        let mut p = *origin;
+        let mut d = direction.unit_vector();
+        let mut ior_before = 1.0;
+        for (interface, z) in self.interfaces.iter().zip(positions)
+        {
+            let ior_after = medium_ior(interface.ior);
+            let (next_p, next_d) = LensSystem::trace_interface(interface, z, &p, &d,
+                                                               ior_before, ior_after)?;
+            p = next_p;
+            d = next_d;
+            ior_before = ior_after;
+        }
+        Some((p, d))
+    }
+
+    // Distance from the rear vertex to the film that brings an object
+    // `focus_distance` millimetres in front of the lens into focus, found by
+    // tracing a near-axial ray from the object and seeing where it crosses
+    // the axis behind the lens
+    pub fn focus(&self, focus_distance: f32) -> Option<f32>
+    {
+        let front_z = self.vertex_positions(0.0)[0];
+        let height = 0.01 * self.interfaces[0].aperture_radius;
+        let object = vec3f::Vec3f32::new_from_points(0.0, 0.0, front_z + focus_distance);
+        let target = vec3f::Vec3f32::new_from_points(height, 0.0, front_z);
+        // Start the ray just in front of the lens, far away objects would
+        // otherwise lose all precision in the sphere intersections
+        let direction = (target - object).unit_vector();
+        let start = target - direction / direction.z.abs();
+        let (p, d) = self.trace_from_scene(0.0, &start, &direction)?;
+        if d.x >= 0.0
+        {
+            // The ray never converges back onto the axis
+            return None;
+        }
+        let t = -p.x / d.x;
+        Some(-(p.z + d.z * t))
+    }
+
+    fn rear_aperture_radius(&self) -> f32
+    {
+        self.interfaces[self.interfaces.len() - 1].aperture_radius
+    }
+
+    pub fn fingerprint(&self, fingerprint: &mut checkpoint::Fingerprint)
+    {
+        for interface in &self.interfaces
+        {
+            fingerprint.f32(interface.radius);
+            fingerprint.f32(interface.thickness);
+            fingerprint.f32(interface.ior);
+            fingerprint.f32(interface.aperture_radius);
+        }
+    }
+}
+
+// Bounds of the rear element area that rays from one ring of film points
+// actually get through the lens from, in the frame of a film point on the
+// +x axis
+#[derive(Copy, Clone)]
+struct PupilBounds
+{
+    low_x: f32,
+    low_y: f32,
+    high_x: f32,
+    high_y: f32,
+}
+
+impl PupilBounds
+{
+    fn area(&self) -> f32
+    {
+        (self.high_x - self.low_x).max(0.0) * (self.high_y - self.low_y).max(0.0)
+    }
+}
+
+// A camera simulating a real lens by tracing rays from the film through
+// every element of its prescription
+pub struct RealisticCamera
+{
+    origin: vec3f::Vec3f32,
+    u: vec3f::Vec3f32,
+    v: vec3f::Vec3f32,
+    w: vec3f::Vec3f32,
+    lens: LensSystem,
+    film_distance: f32,
+    film_width: f32,
+    film_height: f32,
+    film_diagonal: f32,
+    pupils: Vec<PupilBounds>,
+    shutter: camera::Shutter,
+}
+
+impl RealisticCamera
+{
+    // `film_diagonal` is in millimetres (43.3 for full frame) and
+    // `focus_dist` in scene units from the front of the lens. Fails when the
+    // lens cannot bring that distance into focus.
+    pub fn new(look_from: vec3f::Vec3f32, look_at: vec3f::Vec3f32,
+               vup: vec3f::Vec3f32, lens: LensSystem, film_diagonal: f32,
+               aspect: f32, focus_dist: f32) -> Result<RealisticCamera, String>
+    {
+        let (u, v, w) = camera::camera_frame(look_from, look_at, vup);
+        let film_height = film_diagonal / (1.0 + aspect * aspect).sqrt();
+        let film_distance = lens.focus(focus_dist * MILLIMETRES_PER_UNIT)
+            .ok_or_else(|| format!("lens cannot focus at a distance of {}", focus_dist))?;
+        let mut camera = RealisticCamera
+        {
+            origin: look_from,
+            u,
+            v,
+            w,
+            lens,
+            film_distance,
+            film_width: film_height * aspect,
+            film_height,
+            film_diagonal,
+            pupils: Vec::with_capacity(PUPIL_BINS),
+            shutter: camera::Shutter::instant(),
+        };
+        for bin in 0 .. PUPIL_BINS
+        {
+            let r0 = bin as f32 / PUPIL_BINS as f32 * film_diagonal / 2.0;
+            let r1 = (bin + 1) as f32 / PUPIL_BINS as f32 * film_diagonal / 2.0;
+            let bounds = camera.bound_exit_pupil(r0, r1);
+            camera.pupils.push(bounds);
+        }
+        Ok(camera)
+    }
+
+    pub fn with_shutter(mut self, time0: f32, time1: f32) -> RealisticCamera
+    {
+        self.shutter = camera::Shutter { open: time0, close: time1 };
+        self
+    }
+
+    // Traces rays from film points between radii r0 and r1 on the +x axis
+    // towards a grid over the rear element, every film point through the
+    // whole grid, keeping the bounds of the ones that make it out of the
+    // front. The bounds are grown by a grid cell so rays near the edge are
+    // not lost.
+    fn bound_exit_pupil(&self, r0: f32, r1: f32) -> PupilBounds
+    {
+        let rear_radius = 1.5 * self.lens.rear_aperture_radius();
+        let mut bounds = PupilBounds
+        {
+            low_x: f32::MAX,
+            low_y: f32::MAX,
+            high_x: -f32::MAX,
+            high_y: -f32::MAX,
+        };
+        let cell = 2.0 * rear_radius / PUPIL_GRID as f32;
+        for k in 0 .. PUPIL_FILM_SAMPLES
+        {
+            let film_x = r0 + (k as f32 + 0.5) / PUPIL_FILM_SAMPLES as f32 * (r1 - r0);
+            let film = vec3f::Vec3f32::new_from_points(film_x, 0.0, 0.0);
+            for j in 0 .. PUPIL_GRID
+            {
+                for i in 0 .. PUPIL_GRID
+                {
+                    let x = -rear_radius + (i as f32 + 0.5) * cell;
+                    let y = -rear_radius + (j as f32 + 0.5) * cell;
+                    let rear = vec3f::Vec3f32::new_from_points(x, y, self.film_distance);
+                    if self.lens.trace_from_film(self.film_distance, &film,
+                                                 &(rear - film)).is_some()
+                    {
+                        bounds.low_x = bounds.low_x.min(x);
+                        bounds.low_y = bounds.low_y.min(y);
+                        bounds.high_x = bounds.high_x.max(x);
+                        bounds.high_y = bounds.high_y.max(y);
+                    }
+                }
+            }
+        }
+        if bounds.low_x > bounds.high_x
+        {
+            // Nothing gets through from this ring, fall back to the whole
+            // rear element so the camera still reports vignetting honestly
+            return PupilBounds
+            {
+                low_x: -rear_radius,
+                low_y: -rear_radius,
+                high_x: rear_radius,
+                high_y: rear_radius,
+            };
+        }
+        PupilBounds
+        {
+            low_x: bounds.low_x - cell,
+            low_y: bounds.low_y - cell,
+            high_x: bounds.high_x + cell,
+            high_y: bounds.high_y + cell,
+        }
+    }
+}
+
+impl camera::Camera for RealisticCamera
+{
+    fn get_ray(&self, u: f32, v: f32) -> Option<ray::Ray>
+    {
+        self.get_weighted_ray(u, v).map(|(r, _)| r)
+    }
+
+    fn get_weighted_ray(&self, u: f32, v: f32) -> Option<(ray::Ray, vec3f::Vec3f32)>
+    {
+        // The lens flips the image, so the top right of the picture is
+        // formed at the bottom left of the film
+        let film_x = -(u - 0.5) * self.film_width;
+        let film_y = -(v - 0.5) * self.film_height;
+        let film_radius = (film_x * film_x + film_y * film_y).sqrt();
+        let bin = ((film_radius / (self.film_diagonal / 2.0) * PUPIL_BINS as f32) as usize)
+            .min(PUPIL_BINS - 1);
+        let bounds = &self.pupils[bin];
+
+        // Sample the bounds in the frame of a film point on the +x axis and
+        // rotate the sample round to the actual film point
+        let px = bounds.low_x + rng::random() * (bounds.high_x - bounds.low_x);
+        let py = bounds.low_y + rng::random() * (bounds.high_y - bounds.low_y);
+        let (sin, cos) = if film_radius > 0.0
+        {
+            (film_y / film_radius, film_x / film_radius)
+        }
+        else
+        {
+            (0.0, 1.0)
+        };
+        let rear = vec3f::Vec3f32::new_from_points(cos * px - sin * py,
+                                                   sin * px + cos * py,
+                                                   self.film_distance);
+        let film = vec3f::Vec3f32::new_from_points(film_x, film_y, 0.0);
+        let to_rear = rear - film;
+        let (p, d) = self.lens.trace_from_film(self.film_distance, &film, &to_rear)?;
+
+        // Weight by the cos^4 falloff and the size of the sampled pupil,
+        // relative to the centre of the film so exposure stays comparable
+        // with the other cameras
+        let cos_theta = to_rear.z / to_rear.length();
+        let area = bounds.area() / self.pupils[0].area().max(1e-6);
+        let weight = area * cos_theta.powi(4);
+
+        let origin = self.origin + (self.u * p.x + self.v * p.y - self.w * p.z)
+            / MILLIMETRES_PER_UNIT;
+        let direction = self.u * d.x + self.v * d.y - self.w * d.z;
+        Some((ray::Ray::new_at_time(&origin, &direction, self.shutter.sample()),
+              vec3f::Vec3f32::new_from_points(weight, weight, weight)))
+    }
+}
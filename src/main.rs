@@ -3,46 +3,265 @@ mod vec3f;
 mod mat;
 mod ray;
 mod camera;
+mod bokeh;
+mod lens;
+mod stereo;
+mod animation;
+mod film;
+mod tonemap;
+mod hittable;
+mod heightfield;
+mod pgm;
+mod transform;
+mod volume;
+mod optics;
+mod microfacet;
+mod principled;
+mod layered;
+mod subsurface;
+mod voxel;
+mod rng;
+mod sampler;
+mod bluenoise;
+mod checkpoint;
+mod crop;
+mod aov;
+mod denoise;
+mod adaptive;
+mod progressive;
 
+use std::env;
 use std::fs;
+use std::io;
+use std::process;
+use std::time::{Duration, Instant};
 use std::io::Write;
-use rand;
+use hittable::{HitRecord, Hittable, Material, Surface};
 
-enum Material
+struct Sphere
 {
-    Lambertian,
-    Metal,
-    Dielectric,
+    centre: vec3f::Vec3f32,
+    radius: f32,
+    surface: Surface,
 }
 
-struct HitRecord
+impl Hittable for Sphere
 {
-    t: f32,
-    p: vec3f::Vec3f32,
-    normal: vec3f::Vec3f32,
+    fn hit(&self, r: &mut ray::Ray, tmin: f32, tmax: f32,
+           rec: &mut HitRecord) -> bool
+    {
+        hit_sphere(&self.centre, self.radius, r, tmin, tmax, rec)
+    }
+
+    fn surface(&self) -> &Surface
+    {
+        &self.surface
+    }
+
+    fn fingerprint(&self, fingerprint: &mut checkpoint::Fingerprint)
+    {
+        fingerprint.vec3(&self.centre);
+        fingerprint.f32(self.radius);
+        self.surface.fingerprint(fingerprint);
+    }
 }
 
-struct Sphere
+// A sphere whose centre moves linearly from centre0 at time0 to centre1 at
+// time1, so that it is blurred across the camera shutter interval
+struct MovingSphere
 {
-    centre: vec3f::Vec3f32,
+    centre0: vec3f::Vec3f32,
+    centre1: vec3f::Vec3f32,
+    time0: f32,
+    time1: f32,
     radius: f32,
-    material: Material,
-    albedo: vec3f::Vec3f32,
-    fuzz: f32,
-    refraction: f32
+    surface: Surface,
 }
 
-fn random_in_unit_sphere() -> vec3f::Vec3f32
+impl MovingSphere
+{
+    fn centre(&self, time: f32) -> vec3f::Vec3f32
+    {
+        if self.time1 == self.time0
+        {
+            return self.centre0;
+        }
+        // Holds still outside the interval, as keyframed tracks do
+        let time = time.clamp(self.time0, self.time1);
+        self.centre0 + (self.centre1 - self.centre0) *
+            ((time - self.time0) / (self.time1 - self.time0))
+    }
+}
+
+impl Hittable for MovingSphere
+{
+    fn hit(&self, r: &mut ray::Ray, tmin: f32, tmax: f32,
+           rec: &mut HitRecord) -> bool
+    {
+        hit_sphere(&self.centre(r.time()), self.radius, r, tmin, tmax, rec)
+    }
+
+    fn surface(&self) -> &Surface
+    {
+        &self.surface
+    }
+
+    fn fingerprint(&self, fingerprint: &mut checkpoint::Fingerprint)
+    {
+        fingerprint.vec3(&self.centre0);
+        fingerprint.vec3(&self.centre1);
+        fingerprint.f32(self.time0);
+        fingerprint.f32(self.time1);
+        fingerprint.f32(self.radius);
+        self.surface.fingerprint(fingerprint);
+    }
+}
+
+// Everything `color` needs to know about the world besides the ray
+struct Scene
 {
-    let mut p = vec3f::Vec3f32::new_from_points(1.5, 1.5, 1.5);
-    while p.squared_length() >= 1f32
+    objects: Vec<Box<dyn Hittable>>,
+    fog: Option<volume::Fog>,
+    camera: camera::CameraDescription,
+    // Renders a left/right eye pair instead of a single view when set
+    stereo: Option<stereo::StereoRig>,
+    // Path the camera follows when the scene is animated, which holds the
+    // camera still when not set
+    camera_track: Option<animation::Track<animation::CameraKey>>,
+    // Renders a sequence of frames instead of a single still when set
+    animation: Option<animation::Animation>,
+}
+
+// How the image is rendered, as opposed to what is in it
+struct RenderSettings
+{
+    nx: u32,
+    ny: u32,
+    ns: u32,
+    filter: film::PixelFilter,
+    output: tonemap::OutputTransform,
+    // Extra passes written next to the image
+    aovs: Vec<aov::Aov>,
+    // Applied to each rendered view before it is written, guided by the
+    // albedo and normal passes
+    denoiser: Option<denoise::Denoiser>,
+    // Replaces the fixed ns samples per pixel when set
+    adaptive: Option<adaptive::AdaptiveSampling>,
+    // Renders in passes with snapshots written along the way when set
+    progressive: Option<progressive::Progressive>,
+    sampler: sampler::SamplerKind,
+    // Seeds the sampler, whose numbers only depend on the pixel and the
+    // sample index, so that a resumed render carries on exactly as if it had
+    // never stopped
+    seed: u64,
+    // Saves the render state next to the image this often, in seconds
+    checkpoint_seconds: Option<f32>,
+    // Carries on from the saved render state when there is one
+    resume: bool,
+    // Renders only this part of the image when set
+    crop: Option<crop::CropWindow>,
+}
+
+impl RenderSettings
+{
+    // Passes that have to be gathered while rendering: the requested ones
+    // plus the guides the denoiser needs
+    fn gathered_aovs(&self) -> Vec<aov::Aov>
+    {
+        let mut aovs = self.aovs.clone();
+        if self.heatmap() && !aovs.contains(&aov::Aov::SampleCount)
+        {
+            aovs.push(aov::Aov::SampleCount);
+        }
+        if self.denoiser.is_some()
+        {
+            for guide in [aov::Aov::Albedo, aov::Aov::Normal]
+            {
+                if !aovs.contains(&guide)
+                {
+                    aovs.push(guide);
+                }
+            }
+        }
+        aovs
+    }
+
+    // Size of each view as written out, which is smaller than the image the
+    // camera frames when cropping
+    fn view_size(&self) -> (u32, u32)
+    {
+        match self.crop
+        {
+            Some(ref window) => (window.width, window.height),
+            None => (self.nx, self.ny),
+        }
+    }
+
+    fn heatmap(&self) -> bool
+    {
+        self.adaptive.is_some_and(|adaptive| adaptive.heatmap)
+    }
+
+    fn needs_sample(&self, stats: &adaptive::PixelStats) -> bool
     {
-        p = (vec3f::Vec3f32::new_from_points(rand::random::<f32>(),
-                                             rand::random::<f32>(),
-                                             rand::random::<f32>()) * 2.0) -
-            vec3f::Vec3f32::new_from_points(1.0, 1.0, 1.0);
+        match self.adaptive
+        {
+            Some(ref adaptive) => adaptive.needs_sample(stats),
+            None => stats.count < self.ns,
+        }
     }
-    p
+}
+
+// Where the image, or the directory of animation frames, is written
+const OUTPUT_DIR: &str = "/home/justin/Documents/ray/data";
+
+// Scenes that can be picked from the command line
+#[derive(Copy, Clone)]
+enum SceneKind
+{
+    RandomSpheres,
+    Terrain,
+    Motion,
+    Materials,
+    Smoke,
+}
+
+// What the command line asks for in the scene, as opposed to how it is
+// rendered
+struct SceneOptions
+{
+    kind: SceneKind,
+    // Grayscale image the terrain is read from instead of generated
+    heightmap: Option<String>,
+    // Voxel file the smoke scene's density is read from instead of generated
+    volume: Option<String>,
+    // Replaces the density of the scene's fog, adding fog to scenes without
+    // any, or clears it when zero
+    fog_density: Option<f32>,
+    // Replace the scene's projection and field of view
+    projection: Option<camera::Projection>,
+    fov: Option<f32>,
+    stereo: Option<stereo::StereoRig>,
+    // Replace parts of the scene's lens
+    aperture_size: Option<f32>,
+    aperture: Option<bokeh::ApertureShape>,
+    cat_eye: Option<f32>,
+    chromatic_aberration: Option<bokeh::ChromaticAberration>,
+    // Renders this many frames at frames_per_second instead of a still
+    frames: Option<u32>,
+    frames_per_second: f32,
+}
+
+// Maps exactly three draws to the ball, rather than rejecting points of the
+// cube, so that the dimensions the sampler hands out after it stay lined up
+// from one sample to the next
+fn random_in_unit_sphere() -> vec3f::Vec3f32
+{
+    let z = 1.0 - 2.0 * rng::random();
+    let phi = 2.0 * std::f32::consts::PI * rng::random();
+    let radius = rng::random().cbrt();
+    let ring = (1.0 - z * z).max(0.0).sqrt();
+    vec3f::Vec3f32::new_from_points(ring * phi.cos(), ring * phi.sin(), z) * radius
 }
 
 fn dot (first: &vec3f::Vec3f32, second: &vec3f::Vec3f32) -> f32
@@ -85,71 +304,130 @@ fn hit_sphere(center: &vec3f::Vec3f32, radius: f32, r: &mut ray::Ray,
     false
 }
 
-fn color(r: &mut ray::Ray, spheres: &[Sphere],
-         tmin: f32, tmax: f32, depth: i32) -> vec3f::Vec3f32
+// A ray reaching glass from the inside has crossed it since it last went in,
+// so it loses light to absorption over the length of that segment. The
+// albedo of glass is the colour left after one unit of it.
+fn glass_absorption(direction: &vec3f::Vec3f32, rec: &HitRecord, surface: &Surface)
+                    -> vec3f::Vec3f32
 {
-    let mut rec = HitRecord
+    if dot(direction, &rec.normal) > 0.0
     {
-        t: 0.0f32,
-        p: vec3f::Vec3f32::zeroes(),
-        normal: vec3f::Vec3f32::zeroes(),
-    };
+        optics::beer_lambert(&surface.albedo, rec.t * direction.length())
+    }
+    else
+    {
+        vec3f::Vec3f32::new_from_points(1.0, 1.0, 1.0)
+    }
+}
+
+// `aovs` collects what the path saw on the way for the extra passes
+fn color(r: &mut ray::Ray, scene: &Scene,
+         tmin: f32, tmax: f32, depth: i32,
+         aovs: &mut aov::SampleAovs) -> vec3f::Vec3f32
+{
+    let mut rec = HitRecord::zeroes();
     let mut hit_anything = false;
     let mut closest_so_far = tmax;
-    let mut current_sphere = &spheres[0];
-    for i in spheres
+    let mut current_object = &scene.objects[0];
+    let mut current_index = 0;
+    for (index, i) in scene.objects.iter().enumerate()
     {
-        if hit_sphere(&i.centre, i.radius, r, tmin, closest_so_far,
-                      &mut rec)
+        // Every object fills in a fresh record so fields it does not set
+        // cannot leak over from a farther hit
+        let mut temp_rec = HitRecord::zeroes();
+        if i.hit(r, tmin, closest_so_far, &mut temp_rec)
         {
             hit_anything = true;
-            closest_so_far = rec.t;
-            current_sphere = &i;
+            closest_so_far = temp_rec.t;
+            current_object = i;
+            current_index = index;
+            rec = temp_rec;
+        }
+    }
+
+    // Free-flight sampling through the fog: if the sampled distance falls
+    // short of the nearest surface the ray scatters in the fog instead
+    if let Some(ref fog) = scene.fog
+    {
+        if let Some(t) = fog.sample_scatter(r, tmin, closest_so_far)
+        {
+            if depth >= 50
+            {
+                return vec3f::Vec3f32::zeroes();
+            }
+            let p = r.point_at_parameter(&t);
+            let direction = fog.phase.sample(&r.direction().unit_vector());
+            let mut scattered = ray::Ray::new_at_time(&p, &direction, r.time());
+            if depth == 0
+            {
+                aovs.first_attenuation = fog.albedo;
+            }
+            return fog.albedo * color(&mut scattered, scene, tmin, tmax,
+                                      depth + 1, aovs);
         }
     }
 
     if hit_anything
     {
+        let surface = current_object.surface();
         let mut scattered: ray::Ray;
         let attenuation: vec3f::Vec3f32;
-        let scatter = match current_sphere.material
+        let scatter = match surface.material
         {
             Material::Lambertian =>
             {
                 let target = rec.p + rec.normal
                     + random_in_unit_sphere();
-                scattered = ray::Ray::new_from_vector(&rec.p, &(target - rec.p));
-                attenuation = current_sphere.albedo;
+                scattered = ray::Ray::new_at_time(&rec.p, &(target - rec.p), r.time());
+                attenuation = surface.albedo;
                 true
             },
 
-            Material::Metal =>
+            Material::Metal | Material::Conductor(_) =>
             {
-                let unit_direction = r.direction().unit_vector();
-                let reflected = unit_direction -
-                    (rec.normal * 2f32 *
-                     dot(&unit_direction, &rec.normal));
-                scattered = ray::Ray::new_from_vector(&rec.p, &(reflected + (random_in_unit_sphere() * current_sphere.fuzz)));
-                attenuation = current_sphere.albedo;
-                dot(&scattered.direction(), &rec.normal) > 0f32
+                // Reflect off a microfacet normal sampled from the ones
+                // visible from the incoming direction, which leaves the
+                // Fresnel term times G2 / G1 as the weight
+                let wo = -r.direction().unit_vector();
+                let normal = if dot(&wo, &rec.normal) > 0.0 { rec.normal } else { -rec.normal };
+                let frame = microfacet::Frame::new(&normal);
+                let ggx = microfacet::Ggx::from_roughness(surface.fuzz);
+                let wo_local = frame.to_local(&wo);
+                let m = frame.to_world(&ggx.sample_visible_normal(&wo_local, rng::random(),
+                                                                  rng::random()));
+                let wi = optics::reflect(&-wo, &m);
+                scattered = ray::Ray::new_at_time(&rec.p, &wi, r.time());
+                let cosine = dot(&wo, &m);
+                let fresnel = match surface.material
+                {
+                    Material::Conductor(ior) => ior.fresnel(cosine) * surface.albedo,
+                    _ => optics::schlick_tinted(cosine, &surface.albedo),
+                };
+                let wi_local = frame.to_local(&wi);
+                let g1 = ggx.masking(&wo_local);
+                attenuation = if g1 > 0.0
+                {
+                    fresnel * (ggx.masking_shadowing(&wo_local, &wi_local) / g1)
+                }
+                else
+                {
+                    vec3f::Vec3f32::zeroes()
+                };
+                wi_local.z > 0.0
             },
 
             Material::Dielectric =>
             {
-                attenuation = vec3f::Vec3f32::new_from_points(1.0, 1.0, 1.0);
+                attenuation = glass_absorption(&r.direction(), &rec, surface);
                 let outward_normal: vec3f::Vec3f32;
                 let unit_direction = r.direction().unit_vector();
-                let reflected = unit_direction -
-                    (rec.normal * 2f32 *
-                     dot(&unit_direction, &rec.normal));
+                let reflected = optics::reflect(&unit_direction, &rec.normal);
                 let ni_over_nt: f32;
-                let mut refracted = vec3f::Vec3f32::zeroes();
-                let reflect_prob: f32;
                 let cosine: f32;
                 if dot(&r.direction(), &rec.normal) > 0.0
                 {
                     outward_normal = -rec.normal;
-                    ni_over_nt = current_sphere.refraction;
+                    ni_over_nt = surface.refraction;
                     cosine = ni_over_nt * dot(&r.direction(),
                                               &rec.normal) /
                         r.direction().length();
@@ -157,49 +435,168 @@ fn color(r: &mut ray::Ray, spheres: &[Sphere],
                 else
                 {
                     outward_normal = rec.normal;
-                    ni_over_nt = 1.0 / current_sphere.refraction;
+                    ni_over_nt = 1.0 / surface.refraction;
                     cosine = -dot(&r.direction(), &rec.normal) /
                         r.direction().length();
                 }
-                let dt = dot(&unit_direction, &outward_normal);
-                let discriminant = 1.0 - ni_over_nt * ni_over_nt *
-                    (1.0 - dt * dt);
-                if discriminant > 0.0
-                {
-                    refracted = (unit_direction -
-                                 outward_normal * dt) *
-                        ni_over_nt - outward_normal *
-                        discriminant.sqrt();
-                    let mut r0 = (1.0 - current_sphere.refraction)
-                        / (1.0 + current_sphere.refraction);
-                    r0 = r0 * r0;
-                    reflect_prob = r0 + (1.0 - r0) * (1.0 - cosine).powi(5);
+                let refracted = optics::refract(&unit_direction, &outward_normal,
+                                                ni_over_nt);
+                let reflect_prob = match refracted
+                {
+                    Some(_) => optics::schlick(cosine, surface.refraction),
+                    None => 1.0,
+                };
+                match refracted
+                {
+                    Some(refracted) if rng::random() >= reflect_prob =>
+                    {
+                        scattered = ray::Ray::new_at_time(&rec.p, &refracted, r.time());
+                    },
+                    _ =>
+                    {
+                        scattered = ray::Ray::new_at_time(&rec.p, &reflected, r.time());
+                    },
                 }
-                else
+                true
+            },
+
+            Material::RoughDielectric =>
+            {
+                let dielectric = microfacet::RoughDielectric
                 {
-                    reflect_prob = 1.0;
+                    ggx: microfacet::Ggx::from_roughness(surface.fuzz),
+                    eta: surface.refraction,
+                };
+                let frame = microfacet::Frame::new(&rec.normal);
+                let wo = frame.to_local(&-r.direction().unit_vector());
+                match dielectric.sample(&wo, rng::random(), rng::random(), rng::random())
+                {
+                    Some(sample) =>
+                    {
+                        scattered = ray::Ray::new_at_time(&rec.p, &frame.to_world(&sample.wi),
+                                                          r.time());
+                        attenuation = glass_absorption(&r.direction(), &rec, surface) * sample.weight;
+                        true
+                    },
+                    None =>
+                    {
+                        scattered = ray::Ray::new_at_time(&rec.p, &rec.normal, r.time());
+                        attenuation = vec3f::Vec3f32::zeroes();
+                        false
+                    },
                 }
-                if rand::random::<f32>() < reflect_prob
+            },
+
+            Material::Principled(ref parameters) =>
+            {
+                let bsdf = principled::Bsdf::new(parameters, surface.albedo, surface.fuzz,
+                                                 surface.refraction);
+                let wo = -r.direction().unit_vector();
+                // Only surfaces letting light through have an inside, the
+                // rest reflect the same from either side
+                let normal = if bsdf.transmits() || dot(&wo, &rec.normal) > 0.0
                 {
-                    scattered = ray::Ray::new_from_vector(&rec.p, &reflected);
+                    rec.normal
                 }
                 else
                 {
-                    scattered = ray::Ray::new_from_vector(&rec.p, &refracted);
+                    -rec.normal
+                };
+                let frame = microfacet::Frame::new(&normal);
+                match bsdf.sample(&frame.to_local(&wo), rng::random(), rng::random(),
+                                  rng::random(), rng::random())
+                {
+                    Some(sample) =>
+                    {
+                        scattered = ray::Ray::new_at_time(&rec.p, &frame.to_world(&sample.wi),
+                                                          r.time());
+                        attenuation = sample.weight;
+                        true
+                    },
+                    None =>
+                    {
+                        scattered = ray::Ray::new_at_time(&rec.p, &rec.normal, r.time());
+                        attenuation = vec3f::Vec3f32::zeroes();
+                        false
+                    },
+                }
+            },
+
+            Material::Coated(coating) =>
+            {
+                let wo = -r.direction().unit_vector();
+                let normal = if dot(&wo, &rec.normal) > 0.0 { rec.normal } else { -rec.normal };
+                let frame = microfacet::Frame::new(&normal);
+                match coating.sample(&frame.to_local(&wo), &surface.albedo, surface.fuzz,
+                                     surface.refraction)
+                {
+                    Some(sample) =>
+                    {
+                        scattered = ray::Ray::new_at_time(&rec.p, &frame.to_world(&sample.wi),
+                                                          r.time());
+                        attenuation = sample.weight;
+                        true
+                    },
+                    None =>
+                    {
+                        scattered = ray::Ray::new_at_time(&rec.p, &normal, r.time());
+                        attenuation = vec3f::Vec3f32::zeroes();
+                        false
+                    },
+                }
+            },
+
+            Material::Subsurface(ref subsurface) =>
+            {
+                match subsurface.sample(current_object.as_ref(), &rec,
+                                        &r.direction().unit_vector(), r.time())
+                {
+                    Some(exit) =>
+                    {
+                        scattered = ray::Ray::new_at_time(&exit.p, &exit.direction, r.time());
+                        attenuation = exit.weight;
+                        true
+                    },
+                    None =>
+                    {
+                        scattered = ray::Ray::new_at_time(&rec.p, &rec.normal, r.time());
+                        attenuation = vec3f::Vec3f32::zeroes();
+                        false
+                    },
                 }
+            },
+
+            Material::Medium(phase) =>
+            {
+                let direction = phase.sample(&r.direction().unit_vector());
+                scattered = ray::Ray::new_at_time(&rec.p, &direction, r.time());
+                attenuation = surface.albedo;
                 true
             }
         };
 
+        if depth == 0
+        {
+            aovs.hit = true;
+            aovs.depth = rec.t * r.direction().length();
+            aovs.normal = rec.normal;
+            aovs.albedo = surface.albedo;
+            aovs.position = rec.p;
+            aovs.object_id = current_index as u32 + 1;
+            aovs.material_id = surface.material.id();
+            aovs.first_attenuation = if scatter { attenuation } else { vec3f::Vec3f32::zeroes() };
+        }
+        aovs.record_emitted(depth, rec.emitted);
+
         if depth < 50 && scatter
         {
-            return attenuation * color(&mut scattered,
-                                       spheres, tmin, tmax,
-                                       depth + 1);
+            rec.emitted + attenuation * color(&mut scattered,
+                                              scene, tmin, tmax,
+                                              depth + 1, aovs)
         }
         else
         {
-            return vec3f::Vec3f32::zeroes();
+            rec.emitted
         }
     }
     else
@@ -210,166 +607,1517 @@ fn color(r: &mut ray::Ray, spheres: &[Sphere],
             * (1.0 - t);
         let v2 = (vec3f::Vec3f32::new_from_points(0.5, 0.7, 1.0))
             * t;
+        aovs.record_emitted(depth, v1 + v2);
         v1 + v2
     }
 }
 
-fn main() {
-    fs::create_dir_all("../data").unwrap();
-    //let mut file = fs::File::create("j:/rust/data/foo.ppm").unwrap();
-    let mut file = fs::File::create("/home/justin/Documents/ray/data/foo.ppm").unwrap();
+// One camera's image while it is being rendered
+struct View<'a>
+{
+    // Position among the views rendered together, so that each one gets
+    // different samples
+    index: u32,
+    camera: &'a dyn camera::Camera,
+    film: film::Film,
+    aovs: aov::AovBuffers,
+    stats: Vec<adaptive::PixelStats>,
+}
 
-    let mut world: Vec<Sphere> = Vec::new();
-    world.push(
-        Sphere
+impl<'a> View<'a>
+{
+    fn new(index: u32, camera: &'a dyn camera::Camera, settings: &RenderSettings) -> View<'a>
+    {
+        View
         {
-            centre: vec3f::Vec3f32::new_from_points(0.0, -1000.0, 0.0),
-            radius: 1000.0,
-            material: Material::Lambertian,
-            albedo: vec3f::Vec3f32::new_from_points(0.5, 0.5, 0.5),
-            fuzz: 1.0,
-            refraction: 1.0
+            index,
+            camera,
+            film: film::Film::new(settings.nx, settings.ny, settings.filter),
+            aovs: aov::AovBuffers::new(settings.nx, settings.ny, &settings.gathered_aovs()),
+            stats: (0 .. settings.nx * settings.ny).map(|_| adaptive::PixelStats::new()).collect(),
         }
-    );
+    }
 
-    for a in -11 .. 11
+    // Takes one more sample in every pixel that still needs one, returning
+    // whether any pixel did
+    fn sample_pass(&mut self, scene: &Scene, settings: &RenderSettings) -> bool
     {
-        for b in -11 .. 11
+        let nx = settings.nx;
+        let ny = settings.ny;
+        // Samples just outside a crop window still splat into its edge
+        // pixels through the filter, so a margin of the filter radius is
+        // rendered around it and only cut off when the window is extracted
+        let margin = settings.filter.radius.ceil() as u32;
+        let mut sampled = false;
+        for j in (0 .. ny).rev()
         {
-            let a_f = a as f32;
-            let b_f = b as f32;
-            let choose_mat = rand::random::<f32>();
-            let centre = vec3f::Vec3f32::new_from_points(a_f + 0.9 *
-                                                         rand::random::<f32>(),
-                                                         0.2,
-                                                         b_f + 0.9 *
-                                                         rand::random::<f32>());
-            let big_sphere_centre = vec3f::Vec3f32::new_from_points(4.0, 0.2, 0.0);
-            if (centre - big_sphere_centre).length() > 0.9
+            for i in 0 .. nx
             {
-                if choose_mat < 0.8
-                {
-                    // diffuse
-                    world.push(
-                        Sphere
-                        {
-                            centre: centre,
-                            radius: 0.2,
-                            material: Material::Lambertian,
-                            albedo: vec3f::Vec3f32::new_from_points(rand::random::<f32>() *
-                                                                    rand::random::<f32>(),
-                                                                    rand::random::<f32>() *
-                                                                    rand::random::<f32>(),
-                                                                    rand::random::<f32>() *
-                                                                    rand::random::<f32>()),
-                            fuzz: 1.0,
-                            refraction: 1.0
-                        }
-                    );
-                }
-                else if choose_mat < 0.95
+                let stats = &mut self.stats[(j * nx + i) as usize];
+                let outside = settings.crop.as_ref()
+                    .is_some_and(|window| !window.contains(i, j, ny, margin));
+                if outside || !settings.needs_sample(stats)
                 {
-                    // metal
-                    world.push(
-                        Sphere
-                        {
-                            centre: centre,
-                            radius: 0.2,
-                            material: Material::Metal,
-                            albedo: vec3f::Vec3f32::new_from_points(0.5 * (1.0 + rand::random::<f32>()),
-                                                                    0.5 * (1.0 + rand::random::<f32>()),
-                                                                    0.5 * (1.0 + rand::random::<f32>())),
-                            fuzz: 0.5 * rand::random::<f32>(),
-                            refraction: 1.0
-                        }
-                    );
+                    continue;
                 }
-                else
+                // Views are told apart as if stacked one above the other
+                rng::start_sample(i, self.index * ny + ny - 1 - j, stats.count);
+                let x = i as f32 + rng::random();
+                let y = j as f32 + rng::random();
+                let mut col = vec3f::Vec3f32::zeroes();
+                let mut sample = aov::SampleAovs::new();
+                let mut sample_weight = vec3f::Vec3f32::zeroes();
+                if let Some((mut r, weight)) = self.camera.get_weighted_ray(x / nx as f32,
+                                                                           y / ny as f32)
                 {
-                    // glass
-                    world.push(
-                        Sphere
-                        {
-                            centre: centre,
-                            radius: 0.2,
-                            material: Material::Dielectric,
-                            albedo: vec3f::Vec3f32::new_from_points(1.0, 1.0, 1.0),
-                            fuzz: 1.0,
-                            refraction: 1.0 + rand::random::<f32>(),
-                        }
-                    );
+                    col = weight * color(&mut r, scene, 0.001, f32::MAX, 0,
+                                         &mut sample);
+                    sample_weight = weight;
                 }
+                self.film.add_sample(x, y, col);
+                stats.add(&col);
+                self.aovs.add_sample(i, j, &sample, sample_weight, col);
+                sampled = true;
             }
         }
+        sampled
     }
 
-    world.push(
-        Sphere
+    fn encode(&self, encoder: &mut checkpoint::Encoder)
+    {
+        self.film.encode(encoder);
+        self.aovs.encode(encoder);
+        for stats in &self.stats
         {
-            centre: vec3f::Vec3f32::new_from_points(0.0, 1.0, 0.0),
-            radius: 1.0,
-            material: Material::Dielectric,
-            albedo: vec3f::Vec3f32::new_from_points(1.0, 1.0, 1.0),
-            fuzz: 1.0,
-            refraction: 1.5
+            stats.encode(encoder);
         }
-    );
-    world.push(
-        Sphere
+    }
+
+    fn decode(&mut self, decoder: &mut checkpoint::Decoder) -> io::Result<()>
+    {
+        self.film.decode(decoder)?;
+        self.aovs.decode(decoder)?;
+        for stats in &mut self.stats
         {
-            centre: vec3f::Vec3f32::new_from_points(-4.0, 1.0, 0.0),
-            radius: 1.0,
-            material: Material::Lambertian,
-            albedo: vec3f::Vec3f32::new_from_points(0.4, 0.2, 0.1),
-            fuzz: 1.0,
-            refraction: 1.0
+            *stats = adaptive::PixelStats::decode(decoder)?;
         }
-    );
-    world.push(
-        Sphere
+        Ok(())
+    }
+
+    // The image reconstructed from the samples so far with the pixel filter
+    // and cut down to the crop window, denoised if asked for, in rows from
+    // the top down, along with its passes
+    fn resolve(&self, settings: &RenderSettings) -> (Vec<vec3f::Vec3f32>, aov::AovBuffers)
+    {
+        let (pixels, aovs) = match settings.crop
         {
-            centre: vec3f::Vec3f32::new_from_points(4.0, 1.0, 0.0),
-            radius: 1.0,
-            material: Material::Metal,
-            albedo: vec3f::Vec3f32::new_from_points(0.8, 0.6, 0.5),
-            fuzz: 0.0,
-            refraction: 1.0
+            Some(ref window) => (window.extract(&self.film.pixels(), settings.nx),
+                                 self.aovs.crop(window)),
+            None => (self.film.pixels(), self.aovs.clone()),
+        };
+        let (width, height) = settings.view_size();
+        match settings.denoiser
+        {
+            Some(ref denoiser) =>
+            {
+                let features = denoise::Features
+                {
+                    albedo: aovs.pass(aov::Aov::Albedo).unwrap(),
+                    normal: aovs.pass(aov::Aov::Normal).unwrap(),
+                };
+                (denoiser.apply(&pixels, &features, width, height), aovs)
+            },
+            None => (pixels, aovs),
         }
-    );
+    }
+}
 
-    let nx: f32 = 600f32;
-    let ny: f32 = 300f32;
-    let ns: f32 = 100f32;
-    write!(&mut file, "P3\n {} {}\n255\n", nx, ny).unwrap();
-    let look_from = vec3f::Vec3f32::new_from_points(13.0, 2.0, 3.0);
-    let look_at = vec3f::Vec3f32::new_from_points(0.0, 0.0, 0.0);
-    let vup = vec3f::Vec3f32::new_from_points(0.0, 1.0, 0.0);
-    let dist_to_focus = 10.0;
-    let aperature = 0.1;
-    let camera = camera::Camera::new(look_from, look_at,
-                                     vup, 20.0, nx/ny, aperature,
-                                     dist_to_focus);
-    for j in (0 .. ny as u32).rev()
-    {
-        for i in 0 .. nx as u32
-        {
-            let mut col = vec3f::Vec3f32::zeroes();
-            for _s in 0 .. ns as u32
-            {
-                let u = (i as f32 + rand::random::<f32>()) / nx;
-                let v = (j as f32 + rand::random::<f32>()) / ny;
-                let mut r = camera.get_ray(u, v);
-
-                col += color(&mut r, &world, 0.001, f32::MAX, 0);
-            }
+// The views stacked from the first at the top to the last at the bottom,
+// along with their passes and the height of the stack
+fn resolve_views(views: &[View], settings: &RenderSettings)
+                 -> (Vec<vec3f::Vec3f32>, aov::AovBuffers, u32)
+{
+    let (mut pixels, mut aovs) = views[0].resolve(settings);
+    for view in &views[1 ..]
+    {
+        let (view_pixels, view_aovs) = view.resolve(settings);
+        pixels.extend(view_pixels);
+        aovs.extend(view_aovs);
+    }
+    (pixels, aovs, views.len() as u32 * settings.view_size().1)
+}
+
+// Hash of the settings that shape the accumulated samples, of the camera
+// and of every object in the scene
+fn fingerprint(scene: &Scene, description: &camera::CameraDescription,
+               settings: &RenderSettings) -> u64
+{
+    let mut fingerprint = checkpoint::Fingerprint::new();
+    fingerprint.u32(settings.nx);
+    fingerprint.u32(settings.ny);
+    fingerprint.u32(settings.ns);
+    fingerprint.u32(settings.sampler as u32);
+    settings.filter.fingerprint(&mut fingerprint);
+    if let Some(ref window) = settings.crop
+    {
+        fingerprint.u32(window.x);
+        fingerprint.u32(window.y);
+        fingerprint.u32(window.width);
+        fingerprint.u32(window.height);
+    }
+    if let Some(ref adaptive) = settings.adaptive
+    {
+        fingerprint.u32(adaptive.min_samples);
+        fingerprint.u32(adaptive.max_samples);
+        fingerprint.f32(adaptive.threshold);
+    }
+    for aov in settings.gathered_aovs()
+    {
+        fingerprint.u32(aov as u32);
+    }
+
+    description.fingerprint(&mut fingerprint);
+    if let Some(ref rig) = scene.stereo
+    {
+        rig.fingerprint(&mut fingerprint);
+    }
+    if let Some(ref fog) = scene.fog
+    {
+        fog.fingerprint(&mut fingerprint);
+    }
+    fingerprint.u32(scene.objects.len() as u32);
+    for object in &scene.objects
+    {
+        object.fingerprint(&mut fingerprint);
+    }
+    fingerprint.finish()
+}
+
+fn save_checkpoint(path: &str, fingerprint: u64, seed: u64, passes: u32, views: &[View])
+{
+    let mut encoder = checkpoint::Encoder::new();
+    encoder.u64(fingerprint);
+    encoder.u64(seed);
+    encoder.u32(passes);
+    for view in views
+    {
+        view.encode(&mut encoder);
+    }
+    if let Err(error) = checkpoint::save(path, encoder)
+    {
+        eprintln!("could not save checkpoint {}: {}", path, error);
+    }
+}
+
+// Restores the views from a checkpoint made for the same fingerprint,
+// returning the seed and the number of passes already done
+fn load_checkpoint(path: &str, fingerprint: u64, views: &mut [View]) -> io::Result<(u64, u32)>
+{
+    let mut decoder = checkpoint::load(path)?;
+    if decoder.u64()? != fingerprint
+    {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                  "it was made with a different scene or settings"));
+    }
+    let seed = decoder.u64()?;
+    let passes = decoder.u32()?;
+    for view in views
+    {
+        view.decode(&mut decoder)?;
+    }
+    Ok((seed, passes))
+}
 
+// Renders every camera built from `description` in passes of one sample
+// per pixel until each pixel has had ns samples, or has converged when
+// sampling adaptively. In progressive mode `snapshot` is handed the images
+// so far whenever one is due, and rendering stops early once the time
+// budget runs out. The render state is checkpointed next to `image_path`
+// when asked for.
+fn render(scene: &Scene, description: &camera::CameraDescription,
+          cameras: &[&dyn camera::Camera], settings: &RenderSettings, image_path: &str,
+          snapshot: &mut dyn FnMut(&[vec3f::Vec3f32], aov::AovBuffers, u32))
+          -> (Vec<vec3f::Vec3f32>, aov::AovBuffers, u32)
+{
+    let mut views: Vec<View> = cameras.iter().enumerate()
+        .map(|(index, &camera)| View::new(index as u32, camera, settings))
+        .collect();
+    let fingerprint = fingerprint(scene, description, settings);
+    let checkpoint_path = checkpoint::path(image_path);
+    let mut seed = settings.seed;
+    let mut passes = 0;
+    if settings.resume
+    {
+        match load_checkpoint(&checkpoint_path, fingerprint, &mut views)
+        {
+            Ok((saved_seed, saved_passes)) =>
+            {
+                seed = saved_seed;
+                passes = saved_passes;
+            },
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound =>
+                eprintln!("no checkpoint at {}, starting from scratch", checkpoint_path),
+            Err(error) =>
+            {
+                eprintln!("cannot resume from {}: {}", checkpoint_path, error);
+                process::exit(1);
+            },
+        }
+    }
 
-            col /= ns;
-            col.x = col.x.sqrt();
-            col.y = col.y.sqrt();
-            col.z = col.z.sqrt();
-            col *= 255.99f32;
-            col.write_vec_as_int(&mut file);
+    let samples_per_pixel = settings.adaptive.map_or(settings.ns, |adaptive| adaptive.max_samples);
+    rng::set_sampler(settings.sampler.build(seed, samples_per_pixel));
+    let mut schedule = settings.progressive.map(progressive::Schedule::start);
+    let mut last_checkpoint = Instant::now();
+    loop
+    {
+        let mut sampled = false;
+        for view in &mut views
+        {
+            sampled |= view.sample_pass(scene, settings);
+        }
+        if !sampled
+        {
+            break;
+        }
+        passes += 1;
+        if let Some(seconds) = settings.checkpoint_seconds
+        {
+            if last_checkpoint.elapsed() >= Duration::from_secs_f32(seconds)
+            {
+                save_checkpoint(&checkpoint_path, fingerprint, seed, passes, &views);
+                last_checkpoint = Instant::now();
+            }
+        }
+        if let Some(ref mut schedule) = schedule
+        {
+            if schedule.out_of_time()
+            {
+                break;
+            }
+            if schedule.snapshot_due(passes)
+            {
+                let (pixels, aovs, height) = resolve_views(&views, settings);
+                snapshot(&pixels, aovs, height);
+            }
         }
     }
+    if settings.checkpoint_seconds.is_some()
+    {
+        save_checkpoint(&checkpoint_path, fingerprint, seed, passes, &views);
+    }
+    resolve_views(&views, settings)
+}
+
+// Renders the view from one camera description, or both eyes stacked left
+// above right when the scene is stereo, returning the pixels, the extra
+// passes and the image height. Fails when the cameras cannot be built.
+fn render_view(scene: &Scene, description: &camera::CameraDescription,
+               settings: &RenderSettings, image_path: &str,
+               snapshot: &mut dyn FnMut(&[vec3f::Vec3f32], aov::AovBuffers, u32))
+               -> Result<(Vec<vec3f::Vec3f32>, aov::AovBuffers, u32), String>
+{
+    let cameras = match scene.stereo
+    {
+        Some(ref rig) => vec![rig.eye_camera(description, stereo::Eye::Left)?,
+                             rig.eye_camera(description, stereo::Eye::Right)?],
+        None => vec![description.build()?],
+    };
+    let cameras: Vec<&dyn camera::Camera> = cameras.iter().map(|camera| camera.as_ref()).collect();
+    Ok(render(scene, description, &cameras, settings, image_path, snapshot))
+}
+
+// Writes the image along with the requested passes and the sample heatmap.
+// A crop window is pasted into its full frame image when compositing, while
+// the passes and the heatmap always cover just the window.
+fn write_outputs(path: &str, pixels: &[vec3f::Vec3f32], aovs: aov::AovBuffers,
+                 height: u32, settings: &RenderSettings)
+{
+    let (width, view_height) = settings.view_size();
+    let display: Vec<vec3f::Vec3f32> = pixels.iter().map(|pixel| settings.output.apply(pixel)).collect();
+    match settings.crop
+    {
+        Some(crop::CropWindow { composite: Some(ref frame_path), .. }) =>
+        {
+            let window = settings.crop.as_ref().unwrap();
+            let views = height / view_height;
+            let frame_height = views * settings.ny;
+            let mut frame = match pgm::read_ppm(frame_path)
+            {
+                Ok((nx, ny, frame)) if nx as u32 == settings.nx && ny as u32 == frame_height => frame,
+                Ok(_) =>
+                {
+                    eprintln!("{} is not {}x{}, cannot composite into it", frame_path,
+                              settings.nx, frame_height);
+                    process::exit(1);
+                },
+                Err(error) =>
+                {
+                    eprintln!("cannot composite into {}: {}", frame_path, error);
+                    process::exit(1);
+                },
+            };
+            window.composite(&mut frame, settings.nx, settings.ny, &display);
+            let mut file = fs::File::create(path).unwrap();
+            write_image(&mut file, &frame, settings.nx, frame_height);
+        },
+        _ =>
+        {
+            let mut file = fs::File::create(path).unwrap();
+            write_image(&mut file, &display, width, height);
+        },
+    }
+    if let Some(adaptive) = settings.adaptive.filter(|adaptive| adaptive.heatmap)
+    {
+        adaptive::write_heatmap(path, aovs.pass(aov::Aov::SampleCount).unwrap(),
+                                width, height, adaptive.max_samples);
+    }
+    if let Err(error) = aovs.only(&settings.aovs).write(path)
+    {
+        eprintln!("cannot write the passes for {}: {}", path, error);
+        process::exit(1);
+    }
+}
+
+// Writes display encoded pixels, with every channel in [0, 1]
+fn write_image(file: &mut fs::File, pixels: &[vec3f::Vec3f32], nx: u32, ny: u32)
+{
+    write!(file, "P3\n {} {}\n255\n", nx, ny).unwrap();
+    for pixel in pixels
+    {
+        // Offset by half so the truncation when writing rounds to nearest
+        let col = *pixel * 255.0 + 0.5;
+        col.write_vec_as_int(file);
+    }
+}
+
+fn camera_error(error: &str) -> !
+{
+    eprintln!("cannot build the camera: {}", error);
+    process::exit(1);
+}
+
+fn usage() -> !
+{
+    eprintln!("usage: ray [--samples SPP] [--denoise [atrous|bilateral]] \
+               [--adaptive MIN_SPP MAX_SPP THRESHOLD [--heatmap]] \
+               [--progressive [--snapshot-passes N] [--snapshot-seconds S] \
+               [--time-budget S]] [--seed N] [--checkpoint SECONDS] [--resume] \
+               [--crop X Y WIDTH HEIGHT [--composite FRAME.ppm]] \
+               [--sampler independent|stratified|halton|sobol|bluenoise] \
+               [--scene spheres|terrain|motion|materials|smoke] [--heightmap FILE.pgm] \
+               [--volume FILE] [--fog DENSITY] [--projection perspective|orthographic HEIGHT|\
+               fisheye [equidistant|equisolid]|equirectangular|\
+               realistic LENS FILM_DIAGONAL] [--fov DEGREES] \
+               [--aperture-size DIAMETER] \
+               [--aperture circle|polygon BLADES ROTATION|mask FILE.pgm] \
+               [--cat-eye AMOUNT] [--chromatic-aberration LATERAL LONGITUDINAL] \
+               [--stereo INTEROCULAR [--convergence DISTANCE]] \
+               [--frames N [--fps FPS]] \
+               [--filter box|tent|gaussian|mitchell|lanczos] [--filter-radius PIXELS] \
+               [--tonemap clamp|reinhard|aces|agx] [--exposure STOPS] \
+               [--white-balance R G B] \
+               [--aov depth|normal|albedo|object_id|material_id|position|direct|\
+               indirect|sample_count[,...]]");
+    process::exit(2);
+}
+
+// Overrides the built in render settings and scene from the command line
+fn parse_arguments(settings: &mut RenderSettings, options: &mut SceneOptions)
+{
+    fn value<T: std::str::FromStr>(arg: Option<String>) -> T
+    {
+        arg.and_then(|a| a.parse().ok()).unwrap_or_else(|| usage())
+    }
+
+    // Any of the progressive options turns progressive mode on
+    fn progressive_options(settings: &mut RenderSettings) -> &mut progressive::Progressive
+    {
+        settings.progressive.get_or_insert(progressive::Progressive
+        {
+            snapshot_passes: 0,
+            snapshot_seconds: 0.0,
+            time_budget: None,
+        })
+    }
+
+    let mut heatmap = false;
+    let mut composite: Option<String> = None;
+    let mut convergence: Option<f32> = None;
+    let mut fps: Option<f32> = None;
+    let mut filter_radius: Option<f32> = None;
+    let mut args = env::args().skip(1).peekable();
+    while let Some(arg) = args.next()
+    {
+        match arg.as_str()
+        {
+            "--denoise" =>
+            {
+                settings.denoiser = match args.peek().map(|a| a.as_str())
+                {
+                    Some("atrous") => { args.next(); Some(denoise::Denoiser::atrous()) },
+                    Some("bilateral") => { args.next(); Some(denoise::Denoiser::joint_bilateral()) },
+                    _ => Some(denoise::Denoiser::atrous()),
+                };
+            },
+            "--adaptive" =>
+            {
+                settings.adaptive = Some(adaptive::AdaptiveSampling
+                {
+                    min_samples: value(args.next()),
+                    max_samples: value(args.next()),
+                    threshold: value(args.next()),
+                    heatmap: false,
+                });
+            },
+            "--heatmap" => heatmap = true,
+            "--samples" => settings.ns = value(args.next()),
+            "--seed" => settings.seed = value(args.next()),
+            "--sampler" =>
+            {
+                settings.sampler = match args.next().as_deref()
+                {
+                    Some("independent") => sampler::SamplerKind::Independent,
+                    Some("stratified") => sampler::SamplerKind::Stratified,
+                    Some("halton") => sampler::SamplerKind::Halton,
+                    Some("sobol") => sampler::SamplerKind::Sobol,
+                    Some("bluenoise") => sampler::SamplerKind::BlueNoise,
+                    _ => usage(),
+                };
+            },
+            "--checkpoint" => settings.checkpoint_seconds = Some(value(args.next())),
+            "--resume" => settings.resume = true,
+            "--crop" =>
+            {
+                settings.crop = Some(crop::CropWindow
+                {
+                    x: value(args.next()),
+                    y: value(args.next()),
+                    width: value(args.next()),
+                    height: value(args.next()),
+                    composite: None,
+                });
+            },
+            "--composite" => composite = Some(value(args.next())),
+            "--progressive" => { progressive_options(settings); },
+            "--snapshot-passes" =>
+                progressive_options(settings).snapshot_passes = value(args.next()),
+            "--snapshot-seconds" =>
+                progressive_options(settings).snapshot_seconds = value(args.next()),
+            "--time-budget" =>
+                progressive_options(settings).time_budget = Some(value(args.next())),
+            "--scene" =>
+            {
+                options.kind = match args.next().as_deref()
+                {
+                    Some("spheres") => SceneKind::RandomSpheres,
+                    Some("terrain") => SceneKind::Terrain,
+                    Some("motion") => SceneKind::Motion,
+                    Some("materials") => SceneKind::Materials,
+                    Some("smoke") => SceneKind::Smoke,
+                    _ => usage(),
+                };
+            },
+            "--heightmap" => options.heightmap = Some(value(args.next())),
+            "--volume" => options.volume = Some(value(args.next())),
+            "--fog" => options.fog_density = Some(value(args.next())),
+            "--projection" =>
+            {
+                options.projection = Some(match args.next().as_deref()
+                {
+                    Some("perspective") => camera::Projection::Perspective,
+                    Some("orthographic") => camera::Projection::Orthographic(value(args.next())),
+                    Some("fisheye") => match args.peek().map(|a| a.as_str())
+                    {
+                        Some("equisolid") =>
+                        {
+                            args.next();
+                            camera::Projection::Fisheye(camera::FisheyeMapping::Equisolid)
+                        },
+                        Some("equidistant") =>
+                        {
+                            args.next();
+                            camera::Projection::Fisheye(camera::FisheyeMapping::Equidistant)
+                        },
+                        _ => camera::Projection::Fisheye(camera::FisheyeMapping::Equidistant),
+                    },
+                    Some("equirectangular") => camera::Projection::Equirectangular,
+                    Some("realistic") =>
+                    {
+                        let path: String = value(args.next());
+                        match lens::LensSystem::from_file(&path)
+                        {
+                            Ok(lens) => camera::Projection::Realistic
+                            {
+                                lens,
+                                film_diagonal: value(args.next()),
+                            },
+                            Err(error) =>
+                            {
+                                eprintln!("cannot load lens {}: {}", path, error);
+                                process::exit(1);
+                            },
+                        }
+                    },
+                    _ => usage(),
+                });
+            },
+            "--fov" => options.fov = Some(value(args.next())),
+            "--aperture-size" => options.aperture_size = Some(value(args.next())),
+            "--aperture" =>
+            {
+                options.aperture = Some(match args.next().as_deref()
+                {
+                    Some("circle") => bokeh::ApertureShape::Circle,
+                    Some("polygon") => bokeh::ApertureShape::Polygon
+                    {
+                        blades: value(args.next()),
+                        rotation: value(args.next()),
+                    },
+                    Some("mask") =>
+                    {
+                        let path: String = value(args.next());
+                        match bokeh::ApertureMask::from_pgm(&path)
+                        {
+                            Ok(mask) => bokeh::ApertureShape::Mask(mask),
+                            Err(error) =>
+                            {
+                                eprintln!("cannot load aperture mask {}: {}", path, error);
+                                process::exit(1);
+                            },
+                        }
+                    },
+                    _ => usage(),
+                });
+            },
+            "--cat-eye" => options.cat_eye = Some(value(args.next())),
+            "--chromatic-aberration" =>
+            {
+                options.chromatic_aberration = Some(bokeh::ChromaticAberration
+                {
+                    lateral: value(args.next()),
+                    longitudinal: value(args.next()),
+                });
+            },
+            "--stereo" =>
+            {
+                options.stereo = Some(stereo::StereoRig
+                {
+                    interocular: value(args.next()),
+                    convergence: 0.0,
+                });
+            },
+            "--convergence" => convergence = Some(value(args.next())),
+            "--frames" => options.frames = Some(value(args.next())),
+            "--filter" =>
+            {
+                settings.filter = match args.next().as_deref()
+                {
+                    Some("box") => film::PixelFilter::pixel_box(),
+                    Some("tent") => film::PixelFilter::tent(),
+                    Some("gaussian") => film::PixelFilter::gaussian(),
+                    Some("mitchell") => film::PixelFilter::mitchell(),
+                    Some("lanczos") => film::PixelFilter::lanczos(),
+                    _ => usage(),
+                };
+            },
+            "--filter-radius" => filter_radius = Some(value(args.next())),
+            "--tonemap" =>
+            {
+                settings.output.tone_mapper = match args.next().as_deref()
+                {
+                    Some("clamp") => tonemap::ToneMapper::Clamp,
+                    Some("reinhard") => tonemap::ToneMapper::Reinhard,
+                    Some("aces") => tonemap::ToneMapper::AcesFilmic,
+                    Some("agx") => tonemap::ToneMapper::Agx,
+                    _ => usage(),
+                };
+            },
+            "--exposure" => settings.output.exposure = value(args.next()),
+            "--aov" =>
+            {
+                let names: String = value(args.next());
+                for name in names.split(',')
+                {
+                    let aov = aov::Aov::from_name(name).unwrap_or_else(|| usage());
+                    if !settings.aovs.contains(&aov)
+                    {
+                        settings.aovs.push(aov);
+                    }
+                }
+            },
+            "--white-balance" =>
+            {
+                let white = vec3f::Vec3f32::new_from_points(value(args.next()),
+                                                            value(args.next()),
+                                                            value(args.next()));
+                if white.x <= 0.0 || white.y <= 0.0 || white.z <= 0.0
+                {
+                    usage();
+                }
+                settings.output.white = white;
+            },
+            "--fps" => fps = Some(value(args.next())),
+            _ => usage(),
+        }
+    }
+    if heatmap
+    {
+        match settings.adaptive
+        {
+            Some(ref mut adaptive) => adaptive.heatmap = true,
+            None => usage(),
+        }
+    }
+    if let Some(ref mut progressive) = settings.progressive
+    {
+        // Without an interval a progressive render snapshots every pass
+        if progressive.snapshot_passes == 0 && progressive.snapshot_seconds <= 0.0
+        {
+            progressive.snapshot_passes = 1;
+        }
+    }
+    if let Some(ref mut window) = settings.crop
+    {
+        if !window.fits(settings.nx, settings.ny)
+        {
+            usage();
+        }
+        window.composite = composite;
+    }
+    else if composite.is_some()
+    {
+        usage();
+    }
+    if options.heightmap.is_some() && !matches!(options.kind, SceneKind::Terrain)
+    {
+        usage();
+    }
+    if options.volume.is_some() && !matches!(options.kind, SceneKind::Smoke)
+    {
+        usage();
+    }
+    if let Some(distance) = convergence
+    {
+        match options.stereo
+        {
+            Some(ref mut rig) => rig.convergence = distance,
+            None => usage(),
+        }
+    }
+    if let Some(radius) = filter_radius
+    {
+        if radius <= 0.0
+        {
+            usage();
+        }
+        settings.filter.radius = radius;
+    }
+    if let Some(fps) = fps
+    {
+        if options.frames.is_none() || fps <= 0.0
+        {
+            usage();
+        }
+        options.frames_per_second = fps;
+    }
+}
+
+// The cover of Ray Tracing in One Weekend: three big spheres among a field
+// of small random ones
+fn random_spheres_scene(aspect: f32) -> Scene
+{
+    // The random spheres come out the same on every run, so that a render
+    // of them can be resumed
+    rng::reseed(2018);
+    let mut world: Vec<Box<dyn Hittable>> = Vec::new();
+    world.push(
+        Box::new(Sphere
+        {
+            centre: vec3f::Vec3f32::new_from_points(0.0, -1000.0, 0.0),
+            radius: 1000.0,
+            surface: Surface
+            {
+                material: Material::Lambertian,
+                albedo: vec3f::Vec3f32::new_from_points(0.5, 0.5, 0.5),
+                fuzz: 1.0,
+                refraction: 1.0,
+            }
+        })
+    );
+
+    for a in -11 .. 11
+    {
+        for b in -11 .. 11
+        {
+            let a_f = a as f32;
+            let b_f = b as f32;
+            let choose_mat = rng::random();
+            let centre = vec3f::Vec3f32::new_from_points(a_f + 0.9 *
+                                                         rng::random(),
+                                                         0.2,
+                                                         b_f + 0.9 *
+                                                         rng::random());
+            let big_sphere_centre = vec3f::Vec3f32::new_from_points(4.0, 0.2, 0.0);
+            if (centre - big_sphere_centre).length() > 0.9
+            {
+                if choose_mat < 0.8
+                {
+                    // diffuse
+                    world.push(
+                        Box::new(Sphere
+                        {
+                            centre,
+                            radius: 0.2,
+                            surface: Surface
+                            {
+                                material: Material::Lambertian,
+                                albedo: vec3f::Vec3f32::new_from_points(rng::random() *
+                                                                        rng::random(),
+                                                                        rng::random() *
+                                                                        rng::random(),
+                                                                        rng::random() *
+                                                                        rng::random()),
+                                fuzz: 1.0,
+                                refraction: 1.0,
+                            }
+                        })
+                    );
+                }
+                else if choose_mat < 0.95
+                {
+                    // metal
+                    world.push(
+                        Box::new(Sphere
+                        {
+                            centre,
+                            radius: 0.2,
+                            surface: Surface
+                            {
+                                material: Material::Metal,
+                                albedo: vec3f::Vec3f32::new_from_points(0.5 * (1.0 + rng::random()),
+                                                                        0.5 * (1.0 + rng::random()),
+                                                                        0.5 * (1.0 + rng::random())),
+                                fuzz: 0.5 * rng::random(),
+                                refraction: 1.0,
+                            }
+                        })
+                    );
+                }
+                else
+                {
+                    // glass
+                    world.push(
+                        Box::new(Sphere
+                        {
+                            centre,
+                            radius: 0.2,
+                            surface: Surface
+                            {
+                                material: Material::Dielectric,
+                                albedo: vec3f::Vec3f32::new_from_points(1.0, 1.0, 1.0),
+                                fuzz: 1.0,
+                                refraction: 1.0 + rng::random(),
+                            }
+                        })
+                    );
+                }
+            }
+        }
+    }
+
+    world.push(
+        Box::new(Sphere
+        {
+            centre: vec3f::Vec3f32::new_from_points(0.0, 1.0, 0.0),
+            radius: 1.0,
+            surface: Surface
+            {
+                material: Material::Dielectric,
+                albedo: vec3f::Vec3f32::new_from_points(1.0, 1.0, 1.0),
+                fuzz: 1.0,
+                refraction: 1.5,
+            }
+        })
+    );
+    world.push(
+        Box::new(Sphere
+        {
+            centre: vec3f::Vec3f32::new_from_points(-4.0, 1.0, 0.0),
+            radius: 1.0,
+            surface: Surface
+            {
+                material: Material::Lambertian,
+                albedo: vec3f::Vec3f32::new_from_points(0.4, 0.2, 0.1),
+                fuzz: 1.0,
+                refraction: 1.0,
+            }
+        })
+    );
+    world.push(
+        Box::new(Sphere
+        {
+            centre: vec3f::Vec3f32::new_from_points(4.0, 1.0, 0.0),
+            radius: 1.0,
+            surface: Surface
+            {
+                material: Material::Metal,
+                albedo: vec3f::Vec3f32::new_from_points(0.8, 0.6, 0.5),
+                fuzz: 0.0,
+                refraction: 1.0,
+            }
+        })
+    );
+
+    Scene
+    {
+        objects: world,
+        fog: None,
+        camera: camera::CameraDescription
+        {
+            projection: camera::Projection::Perspective,
+            look_from: vec3f::Vec3f32::new_from_points(13.0, 2.0, 3.0),
+            look_at: vec3f::Vec3f32::new_from_points(0.0, 0.0, 0.0),
+            vup: vec3f::Vec3f32::new_from_points(0.0, 1.0, 0.0),
+            fov: 20.0,
+            aspect,
+            aperature: 0.1,
+            focus_dist: 10.0,
+            lens: bokeh::LensEffects::none(),
+            shutter: camera::Shutter::instant(),
+        },
+        stereo: None,
+        camera_track: None,
+        animation: None,
+    }
+}
+
+// Rolling hills with a glass and a metal sphere resting above them and a
+// cloud further off, in a light haze. The hills are read from a grayscale
+// heightmap when one is given.
+fn terrain_scene(heightmap: Option<&str>, aspect: f32) -> Scene
+{
+    const SAMPLES: usize = 128;
+
+    let origin = vec3f::Vec3f32::new_from_points(-20.0, 0.0, -20.0);
+    let size = vec3f::Vec3f32::new_from_points(40.0, 4.0, 40.0);
+    let ground = Surface
+    {
+        material: Material::Lambertian,
+        albedo: vec3f::Vec3f32::new_from_points(0.35, 0.45, 0.25),
+        fuzz: 1.0,
+        refraction: 1.0,
+    };
+    let terrain = match heightmap
+    {
+        Some(path) => match heightfield::Heightfield::from_pgm(path, origin, size, ground)
+        {
+            Ok(terrain) => terrain,
+            Err(error) =>
+            {
+                eprintln!("cannot load heightmap {}: {}", path, error);
+                process::exit(1);
+            },
+        },
+        None =>
+        {
+            let mut heights = Vec::with_capacity(SAMPLES * SAMPLES);
+            for j in 0 .. SAMPLES
+            {
+                for i in 0 .. SAMPLES
+                {
+                    let x = i as f32 / (SAMPLES - 1) as f32 * 2.0 * std::f32::consts::PI;
+                    let z = j as f32 / (SAMPLES - 1) as f32 * 2.0 * std::f32::consts::PI;
+                    let h = 0.5 + 0.3 * (2.0 * x).sin() * (1.5 * z).cos()
+                        + 0.15 * (5.0 * x + 1.0).sin() * (4.0 * z + 2.0).sin();
+                    heights.push(h.clamp(0.0, 1.0));
+                }
+            }
+            heightfield::Heightfield::new(SAMPLES, SAMPLES, heights, origin, size, ground)
+        },
+    };
+
+    let world: Vec<Box<dyn Hittable>> = vec![
+        Box::new(terrain),
+        Box::new(Sphere
+        {
+            centre: vec3f::Vec3f32::new_from_points(-2.0, 6.0, 0.0),
+            radius: 1.5,
+            surface: Surface
+            {
+                material: Material::Dielectric,
+                albedo: vec3f::Vec3f32::new_from_points(1.0, 1.0, 1.0),
+                fuzz: 1.0,
+                refraction: 1.5,
+            }
+        }),
+        Box::new(Sphere
+        {
+            centre: vec3f::Vec3f32::new_from_points(2.5, 6.0, -2.0),
+            radius: 1.5,
+            surface: Surface
+            {
+                material: Material::Metal,
+                albedo: vec3f::Vec3f32::new_from_points(0.8, 0.6, 0.5),
+                fuzz: 0.1,
+                refraction: 1.0,
+            }
+        }),
+        // A low cloud drifting over the far hills
+        Box::new(volume::ConstantMedium::new(
+            Box::new(Sphere
+            {
+                centre: vec3f::Vec3f32::new_from_points(-7.0, 9.0, -10.0),
+                radius: 3.0,
+                surface: Surface
+                {
+                    material: Material::Lambertian,
+                    albedo: vec3f::Vec3f32::new_from_points(1.0, 1.0, 1.0),
+                    fuzz: 1.0,
+                    refraction: 1.0,
+                }
+            }),
+            0.8,
+            vec3f::Vec3f32::new_from_points(0.95, 0.95, 0.95),
+            volume::PhaseFunction::HenyeyGreenstein(0.5))),
+    ];
+    Scene
+    {
+        objects: world,
+        fog: Some(volume::Fog
+        {
+            density: 0.02,
+            albedo: vec3f::Vec3f32::new_from_points(0.9, 0.9, 0.9),
+            phase: volume::PhaseFunction::HenyeyGreenstein(0.3),
+            centre: vec3f::Vec3f32::zeroes(),
+            radius: 30.0,
+        }),
+        camera: camera::CameraDescription
+        {
+            projection: camera::Projection::Perspective,
+            look_from: vec3f::Vec3f32::new_from_points(0.0, 9.0, 18.0),
+            look_at: vec3f::Vec3f32::new_from_points(0.0, 4.0, 0.0),
+            vup: vec3f::Vec3f32::new_from_points(0.0, 1.0, 0.0),
+            fov: 40.0,
+            aspect,
+            aperature: 0.0,
+            focus_dist: 18.7,
+            lens: bokeh::LensEffects::none(),
+            shutter: camera::Shutter::instant(),
+        },
+        stereo: None,
+        camera_track: None,
+        animation: None,
+    }
+}
+
+// Small spheres bouncing over the course of a second around a squashed
+// metal sphere that slides and spins past them, blurred by a shutter open
+// for the whole second. Animated, the first second of frames shows the
+// motion.
+fn motion_scene(aspect: f32) -> Scene
+{
+    rng::reseed(2018);
+    let mut world: Vec<Box<dyn Hittable>> = Vec::new();
+    world.push(
+        Box::new(Sphere
+        {
+            centre: vec3f::Vec3f32::new_from_points(0.0, -1000.0, 0.0),
+            radius: 1000.0,
+            surface: Surface
+            {
+                material: Material::Lambertian,
+                albedo: vec3f::Vec3f32::new_from_points(0.5, 0.5, 0.5),
+                fuzz: 1.0,
+                refraction: 1.0,
+            }
+        })
+    );
+
+    for a in -6 .. 6
+    {
+        for b in -6 .. 6
+        {
+            let centre = vec3f::Vec3f32::new_from_points(a as f32 + 0.9 * rng::random(),
+                                                         0.2,
+                                                         b as f32 + 0.9 * rng::random());
+            if centre.x.abs() < 1.5 && centre.z.abs() < 1.5
+            {
+                continue;
+            }
+            world.push(
+                Box::new(MovingSphere
+                {
+                    centre0: centre,
+                    centre1: centre + vec3f::Vec3f32::new_from_points(0.0, 0.5 * rng::random(), 0.0),
+                    time0: 0.0,
+                    time1: 1.0,
+                    radius: 0.2,
+                    surface: Surface
+                    {
+                        material: Material::Lambertian,
+                        albedo: vec3f::Vec3f32::new_from_points(rng::random() * rng::random(),
+                                                                rng::random() * rng::random(),
+                                                                rng::random() * rng::random()),
+                        fuzz: 1.0,
+                        refraction: 1.0,
+                    }
+                })
+            );
+        }
+    }
+
+    let pose = |time: f32, x: f32, angle: f32| animation::Keyframe
+    {
+        time,
+        value: transform::Pose
+        {
+            translation: vec3f::Vec3f32::new_from_points(x, 0.6, 0.0),
+            rotation: vec3f::Vec3f32::new_from_points(0.0, angle, 0.0),
+            scale: vec3f::Vec3f32::new_from_points(1.5, 0.6, 0.6),
+        },
+    };
+    let track = animation::Track::new(vec![pose(0.0, -1.0, 0.0), pose(0.5, 0.0, 60.0),
+                                           pose(1.0, 1.0, 90.0)],
+                                      animation::Interpolation::CatmullRom);
+    world.push(
+        Box::new(transform::Transform::keyframed(
+            Box::new(Sphere
+            {
+                centre: vec3f::Vec3f32::zeroes(),
+                radius: 1.0,
+                surface: Surface
+                {
+                    material: Material::Metal,
+                    albedo: vec3f::Vec3f32::new_from_points(0.8, 0.6, 0.5),
+                    fuzz: 0.05,
+                    refraction: 1.0,
+                }
+            }),
+            track))
+    );
+
+    // Animated, the camera swings round towards the front of the scene
+    let camera_key = |time: f32, x: f32, y: f32, z: f32| animation::Keyframe
+    {
+        time,
+        value: animation::CameraKey
+        {
+            look_from: vec3f::Vec3f32::new_from_points(x, y, z),
+            look_at: vec3f::Vec3f32::zeroes(),
+            fov: 20.0,
+            focus_dist: 10.0,
+        },
+    };
+    Scene
+    {
+        objects: world,
+        fog: None,
+        camera: camera::CameraDescription
+        {
+            projection: camera::Projection::Perspective,
+            look_from: vec3f::Vec3f32::new_from_points(13.0, 2.0, 3.0),
+            look_at: vec3f::Vec3f32::new_from_points(0.0, 0.0, 0.0),
+            vup: vec3f::Vec3f32::new_from_points(0.0, 1.0, 0.0),
+            fov: 20.0,
+            aspect,
+            aperature: 0.0,
+            focus_dist: 10.0,
+            lens: bokeh::LensEffects::none(),
+            shutter: camera::Shutter { open: 0.0, close: 1.0 },
+        },
+        stereo: None,
+        camera_track: Some(animation::Track::new(
+            vec![camera_key(0.0, 13.0, 2.0, 3.0), camera_key(0.5, 12.0, 2.5, 5.5),
+                 camera_key(1.0, 10.0, 3.0, 8.0)],
+            animation::Interpolation::CatmullRom)),
+        animation: None,
+    }
+}
+
+// Three rows of spheres showing off the materials: principled surfaces in
+// front, in focus, with coated, translucent and frosted ones behind them
+// and gold, copper and aluminium at the back
+fn materials_scene(aspect: f32) -> Scene
+{
+    let vec3 = vec3f::Vec3f32::new_from_points;
+    let ball = |x: f32, z: f32, surface: Surface| -> Box<dyn Hittable>
+    {
+        Box::new(Sphere
+        {
+            centre: vec3(x, 0.5, z),
+            radius: 0.5,
+            surface,
+        })
+    };
+    let principled = |parameters: principled::Principled, albedo: vec3f::Vec3f32,
+                      roughness: f32| Surface
+    {
+        material: Material::Principled(parameters),
+        albedo,
+        fuzz: roughness,
+        refraction: 1.5,
+    };
+
+    let conductor = |ior: optics::ComplexIor, roughness: f32| Surface
+    {
+        material: Material::Conductor(ior),
+        albedo: vec3(1.0, 1.0, 1.0),
+        fuzz: roughness,
+        refraction: 1.0,
+    };
+
+    let standard = principled::Principled::standard();
+    let world: Vec<Box<dyn Hittable>> = vec![
+        Box::new(Sphere
+        {
+            centre: vec3(0.0, -1000.0, 0.0),
+            radius: 1000.0,
+            surface: Surface
+            {
+                material: Material::Lambertian,
+                albedo: vec3(0.5, 0.5, 0.5),
+                fuzz: 1.0,
+                refraction: 1.0,
+            }
+        }),
+        ball(-2.4, 0.0, principled(standard, vec3(0.8, 0.1, 0.1), 0.5)),
+        ball(-1.2, 0.0, principled(principled::Principled { metallic: 1.0, ..standard },
+                                   vec3(1.0, 0.78, 0.34), 0.3)),
+        ball(0.0, 0.0, principled(principled::Principled { clearcoat: 1.0, ..standard },
+                                  vec3(0.1, 0.2, 0.6), 0.6)),
+        ball(1.2, 0.0, principled(principled::Principled { sheen: 1.0, subsurface: 1.0,
+                                                           ..standard },
+                                  vec3(0.5, 0.2, 0.5), 0.8)),
+        ball(2.4, 0.0, principled(principled::Principled { transmission: 1.0, ..standard },
+                                  vec3(0.9, 1.0, 0.9), 0.05)),
+        ball(-2.4, -1.5, Surface
+        {
+            material: Material::Coated(layered::Coating::plastic()),
+            albedo: vec3(0.1, 0.5, 0.1),
+            fuzz: 1.0,
+            refraction: 1.5,
+        }),
+        ball(-1.2, -1.5, Surface
+        {
+            material: Material::Coated(layered::Coating::car_paint()),
+            albedo: vec3(0.6, 0.05, 0.05),
+            fuzz: 0.4,
+            refraction: 1.5,
+        }),
+        ball(0.0, -1.5, Surface
+        {
+            material: Material::Coated(layered::Coating::varnish()),
+            albedo: vec3(0.45, 0.25, 0.1),
+            fuzz: 1.0,
+            refraction: 1.5,
+        }),
+        ball(1.2, -1.5, Surface
+        {
+            material: Material::Subsurface(subsurface::Subsurface::marble(0.2)),
+            albedo: vec3(0.83, 0.79, 0.75),
+            fuzz: 0.2,
+            refraction: 1.5,
+        }),
+        ball(2.4, -1.5, Surface
+        {
+            material: Material::RoughDielectric,
+            albedo: vec3(1.0, 1.0, 1.0),
+            fuzz: 0.3,
+            refraction: 1.5,
+        }),
+        ball(-1.2, -3.0, conductor(optics::ComplexIor::gold(), 0.1)),
+        ball(0.0, -3.0, conductor(optics::ComplexIor::copper(), 0.25)),
+        ball(1.2, -3.0, conductor(optics::ComplexIor::aluminium(), 0.4)),
+    ];
+    Scene
+    {
+        objects: world,
+        fog: None,
+        camera: camera::CameraDescription
+        {
+            projection: camera::Projection::Perspective,
+            look_from: vec3(0.0, 2.5, 6.0),
+            look_at: vec3(0.0, 0.4, -0.75),
+            vup: vec3(0.0, 1.0, 0.0),
+            fov: 35.0,
+            aspect,
+            aperature: 0.15,
+            focus_dist: 6.3,
+            // Six blades turn the out of focus back row into hexagons, cut
+            // into cat's eyes towards the sides of the frame
+            lens: bokeh::LensEffects
+            {
+                aperture: bokeh::ApertureShape::Polygon { blades: 6, rotation: 0.0 },
+                cat_eye: 0.4,
+                chromatic_aberration: None,
+            },
+            shutter: camera::Shutter::instant(),
+        },
+        stereo: None,
+        camera_track: None,
+        animation: None,
+    }
+}
+
+// A plume of smoke rising from a fire on the ground, tracked through a voxel
+// grid. The density is read from a voxel file when one is given, and the
+// fire glows at its base either way.
+fn smoke_scene(volume: Option<&str>, aspect: f32) -> Scene
+{
+    const SAMPLES: usize = 32;
+
+    // Points of the grid in [0, 1], with the plume rising along v
+    let grid = |value: &dyn Fn(f32, f32, f32) -> f32|
+    {
+        let mut values = Vec::with_capacity(SAMPLES * SAMPLES * SAMPLES);
+        for k in 0 .. SAMPLES
+        {
+            for j in 0 .. SAMPLES
+            {
+                for i in 0 .. SAMPLES
+                {
+                    let step = |n: usize| (n as f32 + 0.5) / SAMPLES as f32;
+                    values.push(value(step(i), step(j), step(k)));
+                }
+            }
+        }
+        voxel::VoxelGrid::new(SAMPLES, SAMPLES, SAMPLES, values)
+    };
+    // Distance from the axis of the plume relative to its width, which
+    // widens as the smoke rises and wavers from side to side
+    let spread = |u: f32, v: f32, w: f32|
+    {
+        let axis = 0.5 + 0.08 * (7.0 * v).sin();
+        let width = 0.12 + 0.3 * v;
+        ((u - axis).powi(2) + (w - 0.5).powi(2)).sqrt() / width
+    };
+
+    let density = match volume
+    {
+        Some(path) => match voxel::VoxelGrid::from_file(path)
+        {
+            Ok(density) => density,
+            Err(error) =>
+            {
+                eprintln!("cannot load volume {}: {}", path, error);
+                process::exit(1);
+            },
+        },
+        None => grid(&|u, v, w|
+        {
+            let billows = 0.75 + 0.25 * (13.0 * v + 5.0 * u).sin() * (9.0 * w + 3.0 * v).cos();
+            (1.0 - spread(u, v, w)).max(0.0) * (1.0 - v) * billows
+        }),
+    };
+    let temperatures = grid(&|u, v, w|
+    {
+        if v < 0.25 && spread(u, v, w) < 0.7
+        {
+            1200.0 + 1800.0 * (1.0 - v / 0.25)
+        }
+        else
+        {
+            0.0
+        }
+    });
+
+    let world: Vec<Box<dyn Hittable>> = vec![
+        Box::new(Sphere
+        {
+            centre: vec3f::Vec3f32::new_from_points(0.0, -1000.0, 0.0),
+            radius: 1000.0,
+            surface: Surface
+            {
+                material: Material::Lambertian,
+                albedo: vec3f::Vec3f32::new_from_points(0.5, 0.5, 0.5),
+                fuzz: 1.0,
+                refraction: 1.0,
+            }
+        }),
+        Box::new(voxel::VoxelMedium::new(density, 4.0,
+                                         vec3f::Vec3f32::new_from_points(-1.5, 0.0, -1.5),
+                                         vec3f::Vec3f32::new_from_points(1.5, 4.0, 1.5),
+                                         vec3f::Vec3f32::new_from_points(0.7, 0.7, 0.7),
+                                         volume::PhaseFunction::HenyeyGreenstein(0.3))
+                 .with_emission(voxel::Emission::Temperature
+                 {
+                     temperatures,
+                     scale: 0.1,
+                 })),
+    ];
+    Scene
+    {
+        objects: world,
+        fog: None,
+        camera: camera::CameraDescription
+        {
+            projection: camera::Projection::Perspective,
+            look_from: vec3f::Vec3f32::new_from_points(0.0, 2.5, 10.0),
+            look_at: vec3f::Vec3f32::new_from_points(0.0, 1.8, 0.0),
+            vup: vec3f::Vec3f32::new_from_points(0.0, 1.0, 0.0),
+            fov: 35.0,
+            aspect,
+            aperature: 0.0,
+            focus_dist: 10.0,
+            lens: bokeh::LensEffects::none(),
+            shutter: camera::Shutter::instant(),
+        },
+        stereo: None,
+        camera_track: None,
+        animation: None,
+    }
+}
+
+fn build_scene(options: &SceneOptions, aspect: f32) -> Scene
+{
+    let mut scene = match options.kind
+    {
+        SceneKind::RandomSpheres => random_spheres_scene(aspect),
+        SceneKind::Terrain => terrain_scene(options.heightmap.as_deref(), aspect),
+        SceneKind::Motion => motion_scene(aspect),
+        SceneKind::Materials => materials_scene(aspect),
+        SceneKind::Smoke => smoke_scene(options.volume.as_deref(), aspect),
+    };
+    match options.fog_density
+    {
+        Some(density) if density <= 0.0 => scene.fog = None,
+        Some(density) =>
+        {
+            // Scenes without fog of their own get a sphere of it reaching
+            // well past the camera
+            let camera = &scene.camera;
+            let fog = scene.fog.get_or_insert(volume::Fog
+            {
+                density,
+                albedo: vec3f::Vec3f32::new_from_points(0.9, 0.9, 0.9),
+                phase: volume::PhaseFunction::Isotropic,
+                centre: camera.look_at,
+                radius: 2.0 * (camera.look_from - camera.look_at).length(),
+            });
+            fog.density = density;
+        },
+        None => (),
+    }
+    if let Some(ref projection) = options.projection
+    {
+        // A fisheye sees the whole hemisphere unless told otherwise
+        if let camera::Projection::Fisheye(_) = projection
+        {
+            scene.camera.fov = 180.0;
+        }
+        scene.camera.projection = projection.clone();
+    }
+    if let Some(fov) = options.fov
+    {
+        scene.camera.fov = fov;
+    }
+    if let Some(size) = options.aperture_size
+    {
+        scene.camera.aperature = size;
+    }
+    if let Some(ref aperture) = options.aperture
+    {
+        scene.camera.lens.aperture = aperture.clone();
+    }
+    if let Some(cat_eye) = options.cat_eye
+    {
+        scene.camera.lens.cat_eye = cat_eye;
+    }
+    if options.chromatic_aberration.is_some()
+    {
+        scene.camera.lens.chromatic_aberration = options.chromatic_aberration;
+    }
+    scene.stereo = options.stereo;
+    if let Some(frames) = options.frames
+    {
+        // Without a track the camera holds still while the objects move
+        let camera = &scene.camera;
+        let track = scene.camera_track.clone().unwrap_or_else(||
+            animation::Track::constant(animation::CameraKey
+            {
+                look_from: camera.look_from,
+                look_at: camera.look_at,
+                fov: camera.fov,
+                focus_dist: camera.focus_dist,
+            }));
+        scene.animation = Some(animation::Animation
+        {
+            camera: track,
+            frames,
+            frames_per_second: options.frames_per_second,
+            shutter_fraction: 0.5,
+            output_dir: format!("{}/frames", OUTPUT_DIR),
+        });
+    }
+    scene
+}
+
+fn main() {
+    fs::create_dir_all("../data").unwrap();
+
+    let mut settings = RenderSettings
+    {
+        nx: 600,
+        ny: 300,
+        ns: 100,
+        filter: film::PixelFilter::pixel_box(),
+        output: tonemap::OutputTransform::standard(),
+        aovs: Vec::new(),
+        denoiser: None,
+        adaptive: None,
+        progressive: None,
+        sampler: sampler::SamplerKind::Sobol,
+        seed: rand::random(),
+        checkpoint_seconds: None,
+        resume: false,
+        crop: None,
+    };
+    let mut options = SceneOptions
+    {
+        kind: SceneKind::RandomSpheres,
+        heightmap: None,
+        volume: None,
+        fog_density: None,
+        projection: None,
+        fov: None,
+        stereo: None,
+        aperture_size: None,
+        aperture: None,
+        cat_eye: None,
+        chromatic_aberration: None,
+        frames: None,
+        frames_per_second: 24.0,
+    };
+    parse_arguments(&mut settings, &mut options);
+    let scene = build_scene(&options, settings.nx as f32 / settings.ny as f32);
+
+    match scene.animation
+    {
+        Some(ref animation) =>
+        {
+            fs::create_dir_all(&animation.output_dir).unwrap();
+            for frame in 0 .. animation.frames
+            {
+                let description = animation.frame_camera(&scene.camera, frame);
+                let path = animation.frame_path(frame);
+                let (pixels, aovs, height) = render_view(&scene, &description, &settings, &path,
+                    &mut |pixels, aovs, height| write_outputs(&path, pixels, aovs, height,
+                                                              &settings))
+                    .unwrap_or_else(|error| camera_error(&error));
+                write_outputs(&path, &pixels, aovs, height, &settings);
+            }
+        },
+
+        None =>
+        {
+            //let mut file = fs::File::create("j:/rust/data/foo.ppm").unwrap();
+            let path = format!("{}/foo.ppm", OUTPUT_DIR);
+            let (pixels, aovs, height) = render_view(&scene, &scene.camera, &settings, &path,
+                &mut |pixels, aovs, height| write_outputs(&path, pixels, aovs, height,
+                                                          &settings))
+                .unwrap_or_else(|error| camera_error(&error));
+            write_outputs(&path, &pixels, aovs, height, &settings);
+        },
+    }
 }
@@ -1,3 +1,5 @@
+use crate::vec3f;
+
 #[derive(Debug, PartialEq)]
 pub struct Matrix44f32
 {
@@ -49,6 +51,84 @@ impl Matrix44f32
         }
     }
 
+    pub fn translation(offset: &vec3f::Vec3f32) -> Matrix44f32
+    {
+        Matrix44f32
+        {
+            data: [1f32, 0f32, 0f32, offset.x,
+                   0f32, 1f32, 0f32, offset.y,
+                   0f32, 0f32, 1f32, offset.z,
+                   0f32, 0f32, 0f32, 1f32]
+        }
+    }
+
+    pub fn scaling(factor: &vec3f::Vec3f32) -> Matrix44f32
+    {
+        Matrix44f32
+        {
+            data: [factor.x, 0f32, 0f32, 0f32,
+                   0f32, factor.y, 0f32, 0f32,
+                   0f32, 0f32, factor.z, 0f32,
+                   0f32, 0f32, 0f32, 1f32]
+        }
+    }
+
+    pub fn rotation_x(degrees: f32) -> Matrix44f32
+    {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        Matrix44f32
+        {
+            data: [1f32, 0f32, 0f32, 0f32,
+                   0f32, cos, -sin, 0f32,
+                   0f32, sin, cos, 0f32,
+                   0f32, 0f32, 0f32, 1f32]
+        }
+    }
+
+    pub fn rotation_y(degrees: f32) -> Matrix44f32
+    {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        Matrix44f32
+        {
+            data: [cos, 0f32, sin, 0f32,
+                   0f32, 1f32, 0f32, 0f32,
+                   -sin, 0f32, cos, 0f32,
+                   0f32, 0f32, 0f32, 1f32]
+        }
+    }
+
+    pub fn rotation_z(degrees: f32) -> Matrix44f32
+    {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        Matrix44f32
+        {
+            data: [cos, -sin, 0f32, 0f32,
+                   sin, cos, 0f32, 0f32,
+                   0f32, 0f32, 1f32, 0f32,
+                   0f32, 0f32, 0f32, 1f32]
+        }
+    }
+
+    // Applies the matrix to a point, treating it as a column vector with
+    // w = 1 so the translation is included
+    pub fn transform_point(&self, p: &vec3f::Vec3f32) -> vec3f::Vec3f32
+    {
+        vec3f::Vec3f32::new_from_points(
+            self[0][0] * p.x + self[0][1] * p.y + self[0][2] * p.z + self[0][3],
+            self[1][0] * p.x + self[1][1] * p.y + self[1][2] * p.z + self[1][3],
+            self[2][0] * p.x + self[2][1] * p.y + self[2][2] * p.z + self[2][3])
+    }
+
+    // Applies the matrix to a direction, with w = 0 so the translation is
+    // ignored
+    pub fn transform_vector(&self, v: &vec3f::Vec3f32) -> vec3f::Vec3f32
+    {
+        vec3f::Vec3f32::new_from_points(
+            self[0][0] * v.x + self[0][1] * v.y + self[0][2] * v.z,
+            self[1][0] * v.x + self[1][1] * v.y + self[1][2] * v.z,
+            self[2][0] * v.x + self[2][1] * v.y + self[2][2] * v.z)
+    }
+
     pub fn product(&self, m2: &Matrix44f32) -> Matrix44f32
     {
         let mut ret = Matrix44f32::zeroes();
@@ -0,0 +1,292 @@
+use crate::vec3f;
+use crate::optics;
+
+// Orthonormal frame around a shading normal, with the normal as local z
+pub struct Frame
+{
+    tangent: vec3f::Vec3f32,
+    bitangent: vec3f::Vec3f32,
+    normal: vec3f::Vec3f32,
+}
+
+impl Frame
+{
+    pub fn new(normal: &vec3f::Vec3f32) -> Frame
+    {
+        let (tangent, bitangent) = normal.orthonormal_basis();
+        Frame
+        {
+            tangent,
+            bitangent,
+            normal: *normal,
+        }
+    }
+
+    pub fn to_local(&self, v: &vec3f::Vec3f32) -> vec3f::Vec3f32
+    {
+        vec3f::Vec3f32::new_from_points(v.dot_product(&self.tangent),
+                                        v.dot_product(&self.bitangent),
+                                        v.dot_product(&self.normal))
+    }
+
+    pub fn to_world(&self, v: &vec3f::Vec3f32) -> vec3f::Vec3f32
+    {
+        self.tangent * v.x + self.bitangent * v.y + self.normal * v.z
+    }
+}
+
+// Cosine weighted direction in the hemisphere around local z, for diffuse
+// lobes whose BSDF times cosine over pdf is just their colour
+pub fn sample_cosine_hemisphere(u1: f32, u2: f32) -> vec3f::Vec3f32
+{
+    let r = u1.sqrt();
+    let phi = 2.0 * std::f32::consts::PI * u2;
+    vec3f::Vec3f32::new_from_points(r * phi.cos(), r * phi.sin(), (1.0 - u1).max(0.0).sqrt())
+}
+
+// Isotropic GGX (Trowbridge-Reitz) distribution of microfacet normals. All
+// directions are in the local frame of the macro surface and point away
+// from it.
+#[derive(Copy, Clone)]
+pub struct Ggx
+{
+    pub alpha: f32,
+}
+
+impl Ggx
+{
+    // Perceptually linear roughness in [0, 1], squared into alpha. A little
+    // roughness is always kept so the maths stays finite for mirrors.
+    pub fn from_roughness(roughness: f32) -> Ggx
+    {
+        Ggx { alpha: (roughness * roughness).clamp(1e-3, 1.0) }
+    }
+
+    pub fn distribution(&self, m: &vec3f::Vec3f32) -> f32
+    {
+        if m.z <= 0.0
+        {
+            return 0.0;
+        }
+        let a2 = self.alpha * self.alpha;
+        let cos2 = m.z * m.z;
+        let denominator = cos2 * (a2 - 1.0) + 1.0;
+        a2 / (std::f32::consts::PI * denominator * denominator)
+    }
+
+    // Smith's auxiliary function
+    fn lambda(&self, w: &vec3f::Vec3f32) -> f32
+    {
+        let cos2 = w.z * w.z;
+        if cos2 <= 0.0
+        {
+            return f32::MAX;
+        }
+        let tan2 = (1.0 - cos2).max(0.0) / cos2;
+        0.5 * ((1.0 + self.alpha * self.alpha * tan2).sqrt() - 1.0)
+    }
+
+    // Fraction of the microfacets facing `w` that it sees unobstructed
+    pub fn masking(&self, w: &vec3f::Vec3f32) -> f32
+    {
+        if w.z <= 0.0
+        {
+            return 0.0;
+        }
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    // Height correlated masking and shadowing for a pair of directions
+    pub fn masking_shadowing(&self, wo: &vec3f::Vec3f32, wi: &vec3f::Vec3f32) -> f32
+    {
+        if wo.z <= 0.0 || wi.z <= 0.0
+        {
+            return 0.0;
+        }
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // Samples a microfacet normal from those visible from `wo`, in
+    // proportion to how much of `wo`'s view each one takes up (Heitz 2018)
+    pub fn sample_visible_normal(&self, wo: &vec3f::Vec3f32, u1: f32, u2: f32) -> vec3f::Vec3f32
+    {
+        // Stretch to the hemisphere configuration
+        let vh = vec3f::Vec3f32::new_from_points(self.alpha * wo.x, self.alpha * wo.y, wo.z)
+            .unit_vector();
+        let length2 = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if length2 > 0.0
+        {
+            vec3f::Vec3f32::new_from_points(-vh.y, vh.x, 0.0) / length2.sqrt()
+        }
+        else
+        {
+            vec3f::Vec3f32::new_from_points(1.0, 0.0, 0.0)
+        };
+        let t2 = vh.cross_product(&t1);
+
+        // Uniform point on the disk, squashed onto the visible half
+        let r = u1.sqrt();
+        let phi = 2.0 * std::f32::consts::PI * u2;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * r * phi.sin();
+        let nh = t1 * p1 + t2 * p2 + vh * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+
+        // Unstretch
+        vec3f::Vec3f32::new_from_points(self.alpha * nh.x, self.alpha * nh.y, nh.z.max(1e-6))
+            .unit_vector()
+    }
+
+    // Density of `sample_visible_normal` picking `m`
+    pub fn visible_normal_pdf(&self, wo: &vec3f::Vec3f32, m: &vec3f::Vec3f32) -> f32
+    {
+        if wo.z <= 0.0
+        {
+            return 0.0;
+        }
+        self.masking(wo) * wo.dot_product(m).max(0.0) * self.distribution(m) / wo.z
+    }
+}
+
+// Walter et al.'s rough dielectric interface, reflecting and transmitting
+// through GGX microfacets. Directions are in the local frame of the surface,
+// with z pointing out of the object whose refractive index relative to the
+// outside is `eta`. Light crossing the interface is not rescaled by the
+// squared ratio of refractive indices, the same as the smooth dielectric.
+#[derive(Copy, Clone)]
+pub struct RoughDielectric
+{
+    pub ggx: Ggx,
+    pub eta: f32,
+}
+
+// Where a sampled direction went and the BSDF times cosine over pdf, or
+// for a walk through layers the throughput it carried
+pub struct BsdfSample
+{
+    pub wi: vec3f::Vec3f32,
+    pub weight: vec3f::Vec3f32,
+}
+
+impl RoughDielectric
+{
+    // Flips both directions so that `wo` is above the surface, returning
+    // them along with the refractive index on wo's side and on the other
+    fn oriented(&self, wo: &vec3f::Vec3f32, wi: &vec3f::Vec3f32)
+                -> (vec3f::Vec3f32, vec3f::Vec3f32, f32, f32)
+    {
+        if wo.z > 0.0
+        {
+            (*wo, *wi, 1.0, self.eta)
+        }
+        else
+        {
+            (-*wo, -*wi, self.eta, 1.0)
+        }
+    }
+
+    pub fn sample(&self, wo: &vec3f::Vec3f32, u1: f32, u2: f32, u3: f32) -> Option<BsdfSample>
+    {
+        let (wo_up, _, eta_o, eta_t) = self.oriented(wo, wo);
+        if wo_up.z == 0.0
+        {
+            return None;
+        }
+        let m = self.ggx.sample_visible_normal(&wo_up, u1, u2);
+        let cosine = wo_up.dot_product(&m);
+        let fresnel = optics::fresnel_dielectric(cosine, eta_t / eta_o);
+        let g1 = self.ggx.masking(&wo_up);
+
+        let wi_up = if u3 < fresnel
+        {
+            let wi = optics::reflect(&-wo_up, &m);
+            if wi.z <= 0.0
+            {
+                return None;
+            }
+            wi
+        }
+        else
+        {
+            // Fresnel is one under total internal reflection, so refraction
+            // always succeeds here
+            let wi = optics::refract(&-wo_up, &m, eta_o / eta_t)?;
+            if wi.z >= 0.0
+            {
+                return None;
+            }
+            wi
+        };
+        let weight = self.ggx.masking_shadowing(&wo_up, &vec3f::Vec3f32::new_from_points(
+            wi_up.x, wi_up.y, wi_up.z.abs())) / g1;
+        let wi = if wo.z > 0.0 { wi_up } else { -wi_up };
+        Some(BsdfSample { wi, weight: vec3f::Vec3f32::new_from_points(weight, weight, weight) })
+    }
+
+    // Half vector facing wo along with the Fresnel reflectance and the
+    // change of variables from it to wi, or None when no microfacet could
+    // have turned wo into wi
+    fn half_vector(&self, wo: &vec3f::Vec3f32, wi: &vec3f::Vec3f32, eta_o: f32, eta_t: f32)
+                   -> Option<(vec3f::Vec3f32, f32, f32)>
+    {
+        if wi.z > 0.0
+        {
+            let h = (*wo + *wi).unit_vector();
+            let fresnel = optics::fresnel_dielectric(wo.dot_product(&h), eta_t / eta_o);
+            Some((h, fresnel, 1.0 / (4.0 * wo.dot_product(&h))))
+        }
+        else
+        {
+            let mut h = -(*wo * eta_o + *wi * eta_t).unit_vector();
+            if h.z < 0.0
+            {
+                h = -h;
+            }
+            let wo_h = wo.dot_product(&h);
+            let wi_h = wi.dot_product(&h);
+            if wo_h <= 0.0 || wi_h >= 0.0
+            {
+                return None;
+            }
+            let fresnel = optics::fresnel_dielectric(wo_h, eta_t / eta_o);
+            let denominator = eta_o * wo_h + eta_t * wi_h;
+            Some((h, fresnel, eta_t * eta_t * -wi_h / (denominator * denominator)))
+        }
+    }
+
+    // BSDF value for light arriving from wi and leaving towards wo
+    pub fn evaluate(&self, wo: &vec3f::Vec3f32, wi: &vec3f::Vec3f32) -> f32
+    {
+        let (wo, wi, eta_o, eta_t) = self.oriented(wo, wi);
+        if wo.z == 0.0 || wi.z == 0.0
+        {
+            return 0.0;
+        }
+        let Some((h, fresnel, jacobian)) = self.half_vector(&wo, &wi, eta_o, eta_t) else
+        {
+            return 0.0;
+        };
+        let wi_up = vec3f::Vec3f32::new_from_points(wi.x, wi.y, wi.z.abs());
+        let dg = self.ggx.distribution(&h) * self.ggx.masking_shadowing(&wo, &wi_up);
+        // Both lobes are D G |wo.h| jacobian / (|wo.z| |wi.z|), weighted by
+        // how much Fresnel sends each way
+        let lobe = if wi.z > 0.0 { fresnel } else { 1.0 - fresnel };
+        lobe * dg * wo.dot_product(&h) * jacobian / (wo.z * wi.z.abs())
+    }
+
+    // Density of `sample` returning wi, for multiple importance sampling
+    pub fn pdf(&self, wo: &vec3f::Vec3f32, wi: &vec3f::Vec3f32) -> f32
+    {
+        let (wo, wi, eta_o, eta_t) = self.oriented(wo, wi);
+        if wo.z == 0.0 || wi.z == 0.0
+        {
+            return 0.0;
+        }
+        let Some((h, fresnel, jacobian)) = self.half_vector(&wo, &wi, eta_o, eta_t) else
+        {
+            return 0.0;
+        };
+        let lobe = if wi.z > 0.0 { fresnel } else { 1.0 - fresnel };
+        self.ggx.visible_normal_pdf(&wo, &h) * lobe * jacobian
+    }
+}
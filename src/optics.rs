@@ -0,0 +1,159 @@
+use crate::vec3f;
+use crate::checkpoint;
+
+// Mirror reflection of `v` about the plane with normal `n`
+pub fn reflect(v: &vec3f::Vec3f32, n: &vec3f::Vec3f32) -> vec3f::Vec3f32
+{
+    *v - (*n * 2f32 * v.dot_product(n))
+}
+
+// Snell's law for the unit direction `v` crossing an interface whose
+// normal `n` faces the incoming side, with ni_over_nt the ratio of the
+// refractive index being left to the one being entered. None means total
+// internal reflection.
+pub fn refract(v: &vec3f::Vec3f32, n: &vec3f::Vec3f32,
+               ni_over_nt: f32) -> Option<vec3f::Vec3f32>
+{
+    let dt = v.dot_product(n);
+    let discriminant = 1.0 - ni_over_nt * ni_over_nt * (1.0 - dt * dt);
+    if discriminant > 0.0
+    {
+        Some((*v - *n * dt) * ni_over_nt - *n * discriminant.sqrt())
+    }
+    else
+    {
+        None
+    }
+}
+
+// Schlick's approximation of the Fresnel reflectance of a dielectric with
+// refractive index `refraction` for the given cosine of incidence
+pub fn schlick(cosine: f32, refraction: f32) -> f32
+{
+    let mut r0 = (1.0 - refraction) / (1.0 + refraction);
+    r0 = r0 * r0;
+    r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
+}
+
+// Schlick's approximation with the reflectance at normal incidence given
+// per channel, as for tinted metals
+pub fn schlick_tinted(cosine: f32, r0: &vec3f::Vec3f32) -> vec3f::Vec3f32
+{
+    let weight = (1.0 - cosine.clamp(0.0, 1.0)).powi(5);
+    *r0 + (vec3f::Vec3f32::new_from_points(1.0, 1.0, 1.0) - *r0) * weight
+}
+
+// Complex refractive index eta + ik of a conductor, per RGB channel
+#[derive(Copy, Clone)]
+pub struct ComplexIor
+{
+    pub eta: vec3f::Vec3f32,
+    pub k: vec3f::Vec3f32,
+}
+
+// Exact Fresnel reflectance of unpolarised light at a conductor
+fn fresnel_conductor_channel(cosine: f32, eta: f32, k: f32) -> f32
+{
+    let cos2 = cosine * cosine;
+    let sin2 = 1.0 - cos2;
+    let t0 = eta * eta - k * k - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let t2 = 2.0 * a * cosine;
+    let rs = (t1 - t2) / (t1 + t2);
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    0.5 * (rp + rs)
+}
+
+impl ComplexIor
+{
+    // Measured values sampled at roughly 650, 550 and 450nm
+    pub fn gold() -> ComplexIor
+    {
+        ComplexIor
+        {
+            eta: vec3f::Vec3f32::new_from_points(0.143, 0.374, 1.442),
+            k: vec3f::Vec3f32::new_from_points(3.983, 2.385, 1.603),
+        }
+    }
+
+    pub fn silver() -> ComplexIor
+    {
+        ComplexIor
+        {
+            eta: vec3f::Vec3f32::new_from_points(0.155, 0.117, 0.138),
+            k: vec3f::Vec3f32::new_from_points(4.828, 3.122, 2.147),
+        }
+    }
+
+    pub fn copper() -> ComplexIor
+    {
+        ComplexIor
+        {
+            eta: vec3f::Vec3f32::new_from_points(0.200, 0.924, 1.102),
+            k: vec3f::Vec3f32::new_from_points(3.912, 2.452, 2.142),
+        }
+    }
+
+    pub fn aluminium() -> ComplexIor
+    {
+        ComplexIor
+        {
+            eta: vec3f::Vec3f32::new_from_points(1.657, 0.880, 0.521),
+            k: vec3f::Vec3f32::new_from_points(9.224, 6.270, 4.837),
+        }
+    }
+
+    pub fn iron() -> ComplexIor
+    {
+        ComplexIor
+        {
+            eta: vec3f::Vec3f32::new_from_points(2.911, 2.950, 2.585),
+            k: vec3f::Vec3f32::new_from_points(3.089, 2.932, 2.767),
+        }
+    }
+
+    pub fn fresnel(&self, cosine: f32) -> vec3f::Vec3f32
+    {
+        let cosine = cosine.clamp(0.0, 1.0);
+        vec3f::Vec3f32::new_from_points(fresnel_conductor_channel(cosine, self.eta.x, self.k.x),
+                                        fresnel_conductor_channel(cosine, self.eta.y, self.k.y),
+                                        fresnel_conductor_channel(cosine, self.eta.z, self.k.z))
+    }
+
+    pub fn fingerprint(&self, fingerprint: &mut checkpoint::Fingerprint)
+    {
+        fingerprint.vec3(&self.eta);
+        fingerprint.vec3(&self.k);
+    }
+}
+
+// Exact Fresnel reflectance of unpolarised light leaving a medium for one
+// whose refractive index is `eta` times higher, for the given cosine of
+// incidence. Total internal reflection gives 1.
+pub fn fresnel_dielectric(cosine: f32, eta: f32) -> f32
+{
+    let cos_i = cosine.clamp(0.0, 1.0);
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0
+    {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let rs = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let rp = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    0.5 * (rs * rs + rp * rp)
+}
+
+// Beer-Lambert absorption: the fraction of light left after travelling
+// `distance` through a medium that lets `transmittance` through per unit
+// distance, per channel
+pub fn beer_lambert(transmittance: &vec3f::Vec3f32, distance: f32) -> vec3f::Vec3f32
+{
+    vec3f::Vec3f32::new_from_points(transmittance.x.powf(distance),
+                                    transmittance.y.powf(distance),
+                                    transmittance.z.powf(distance))
+}
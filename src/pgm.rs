@@ -0,0 +1,151 @@
+use std::fs;
+use std::io;
+
+use crate::vec3f;
+
+fn invalid_data(message: &str) -> io::Error
+{
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// Reads the next whitespace separated header token of a netpbm file,
+// skipping comments, and leaves `pos` on the byte after the token
+fn next_token<'a>(data: &'a [u8], pos: &mut usize) -> io::Result<&'a str>
+{
+    loop
+    {
+        while *pos < data.len() && data[*pos].is_ascii_whitespace()
+        {
+            *pos += 1;
+        }
+        if *pos < data.len() && data[*pos] == b'#'
+        {
+            while *pos < data.len() && data[*pos] != b'\n'
+            {
+                *pos += 1;
+            }
+        }
+        else
+        {
+            break;
+        }
+    }
+    let start = *pos;
+    while *pos < data.len() && !data[*pos].is_ascii_whitespace()
+    {
+        *pos += 1;
+    }
+    if start == *pos
+    {
+        return Err(invalid_data("unexpected end of pgm header"));
+    }
+    std::str::from_utf8(&data[start .. *pos])
+        .map_err(|_| invalid_data("pgm header is not ascii"))
+}
+
+fn next_number(data: &[u8], pos: &mut usize) -> io::Result<usize>
+{
+    next_token(data, pos)?
+        .parse::<usize>()
+        .map_err(|_| invalid_data("malformed number in pgm file"))
+}
+
+// Reads the raster of an 8 or 16 bit ascii (P2, P3) or binary (P5, P6)
+// netpbm file, returning its width, height, number of channels and samples
+// scaled to [0, 1] in rows from the top of the image
+fn read_netpbm(path: &str) -> io::Result<(usize, usize, usize, Vec<f32>)>
+{
+    let data = fs::read(path)?;
+    let mut pos = 0;
+    let magic = next_token(&data, &mut pos)?.to_string();
+    let (channels, binary) = match magic.as_str()
+    {
+        "P2" => (1, false),
+        "P3" => (3, false),
+        "P5" => (1, true),
+        "P6" => (3, true),
+        _ => return Err(invalid_data("not a P2, P3, P5 or P6 netpbm file")),
+    };
+    let width = next_number(&data, &mut pos)?;
+    let height = next_number(&data, &mut pos)?;
+    let max_value = next_number(&data, &mut pos)?;
+    if width == 0 || height == 0 || max_value == 0 || max_value > 65535
+    {
+        return Err(invalid_data("unsupported pgm dimensions"));
+    }
+
+    // The header is untrusted, so the raster size is checked against the
+    // data actually there before anything is allocated for it
+    let count = width.checked_mul(height)
+        .and_then(|n| n.checked_mul(channels))
+        .ok_or_else(|| invalid_data("pgm dimensions are too large"))?;
+    let values = if binary
+    {
+        // Exactly one whitespace byte separates the header from the raster
+        pos += 1;
+        let bytes_per_sample = if max_value < 256 { 1 } else { 2 };
+        let end = count.checked_mul(bytes_per_sample)
+            .and_then(|n| n.checked_add(pos));
+        if end.is_none_or(|end| data.len() < end)
+        {
+            return Err(invalid_data("truncated pgm raster"));
+        }
+        let mut values = Vec::with_capacity(count);
+        for k in 0 .. count
+        {
+            let offset = pos + k * bytes_per_sample;
+            let value = if bytes_per_sample == 1
+            {
+                data[offset] as usize
+            }
+            else
+            {
+                ((data[offset] as usize) << 8) | data[offset + 1] as usize
+            };
+            values.push(value as f32 / max_value as f32);
+        }
+        values
+    }
+    else
+    {
+        // Every ascii sample takes up at least one byte
+        if count > data.len().saturating_sub(pos)
+        {
+            return Err(invalid_data("truncated pgm raster"));
+        }
+        let mut values = Vec::with_capacity(count);
+        for _ in 0 .. count
+        {
+            values.push(next_number(&data, &mut pos)? as f32 /
+                        max_value as f32);
+        }
+        values
+    };
+
+    Ok((width, height, channels, values))
+}
+
+// Loads an 8 or 16 bit grayscale image in ascii (P2) or binary (P5)
+// netpbm format, returning its width, height and samples scaled to [0, 1]
+// in rows from the top of the image
+pub fn read(path: &str) -> io::Result<(usize, usize, Vec<f32>)>
+{
+    match read_netpbm(path)?
+    {
+        (width, height, 1, values) => Ok((width, height, values)),
+        _ => Err(invalid_data("not a P2 or P5 pgm file")),
+    }
+}
+
+// Loads an 8 or 16 bit colour image in ascii (P3) or binary (P6) netpbm
+// format, the same way as `read`
+pub fn read_ppm(path: &str) -> io::Result<(usize, usize, Vec<vec3f::Vec3f32>)>
+{
+    match read_netpbm(path)?
+    {
+        (width, height, 3, values) => Ok((width, height, values.chunks(3)
+            .map(|c| vec3f::Vec3f32::new_from_points(c[0], c[1], c[2]))
+            .collect())),
+        _ => Err(invalid_data("not a P3 or P6 ppm file")),
+    }
+}
@@ -0,0 +1,326 @@
+use crate::vec3f;
+use crate::optics;
+use crate::microfacet;
+use crate::checkpoint;
+
+// Parameters of the Disney principled BSDF (Burley 2012 and 2015), all from
+// 0 to 1. The base colour, roughness and refractive index are the albedo,
+// fuzz and refraction of the surface.
+#[derive(Copy, Clone)]
+pub struct Principled
+{
+    pub metallic: f32,
+    // Reflectance at normal incidence of the non-metallic base scaled into
+    // [0, 0.08], so that 0.5 is the 4% of common dielectrics
+    pub specular: f32,
+    // How far that reflection takes on the base colour
+    pub specular_tint: f32,
+    // Extra reflection at grazing angles, for cloth
+    pub sheen: f32,
+    pub sheen_tint: f32,
+    // Strength of a second, colourless specular layer on top, like varnish
+    pub clearcoat: f32,
+    pub clearcoat_gloss: f32,
+    // How much of the non-metallic base is glass instead of diffuse
+    pub transmission: f32,
+    // Flattens the diffuse lobe towards the look of light scattered just
+    // beneath the surface
+    pub subsurface: f32,
+}
+
+impl Principled
+{
+    // Disney's defaults, which are a plain diffuse dielectric
+    pub fn standard() -> Principled
+    {
+        Principled
+        {
+            metallic: 0.0,
+            specular: 0.5,
+            specular_tint: 0.0,
+            sheen: 0.0,
+            sheen_tint: 0.5,
+            clearcoat: 0.0,
+            clearcoat_gloss: 1.0,
+            transmission: 0.0,
+            subsurface: 0.0,
+        }
+    }
+
+    pub fn fingerprint(&self, fingerprint: &mut checkpoint::Fingerprint)
+    {
+        for value in [self.metallic, self.specular, self.specular_tint, self.sheen,
+                      self.sheen_tint, self.clearcoat, self.clearcoat_gloss,
+                      self.transmission, self.subsurface]
+        {
+            fingerprint.f32(value);
+        }
+    }
+}
+
+const DIFFUSE: usize = 0;
+const SPECULAR: usize = 1;
+const CLEARCOAT: usize = 2;
+const GLASS: usize = 3;
+
+fn luminance(colour: &vec3f::Vec3f32) -> f32
+{
+    0.3 * colour.x + 0.6 * colour.y + 0.1 * colour.z
+}
+
+fn lerp(a: &vec3f::Vec3f32, b: &vec3f::Vec3f32, t: f32) -> vec3f::Vec3f32
+{
+    *a * (1.0 - t) + *b * t
+}
+
+fn schlick_weight(cosine: f32) -> f32
+{
+    (1.0 - cosine.clamp(0.0, 1.0)).powi(5)
+}
+
+// Berry's distribution, which has the long tail of the clear coat
+fn gtr1(cos_h: f32, alpha: f32) -> f32
+{
+    if alpha >= 1.0
+    {
+        return std::f32::consts::FRAC_1_PI;
+    }
+    let a2 = alpha * alpha;
+    let t = 1.0 + (a2 - 1.0) * cos_h * cos_h;
+    (a2 - 1.0) / (std::f32::consts::PI * a2.ln() * t)
+}
+
+// The principled BSDF at one point, with its lobes weighted and ready to
+// sample. Directions are in the local frame of the surface, with z pointing
+// out of the object. Following Blender, the glass lobe brings its own
+// reflection and so takes the place of the specular lobe where it is used.
+pub struct Bsdf
+{
+    parameters: Principled,
+    base: vec3f::Vec3f32,
+    // Hue and saturation of the base colour at unit luminance
+    tint: vec3f::Vec3f32,
+    roughness: f32,
+    ggx: microfacet::Ggx,
+    glass: microfacet::RoughDielectric,
+    specular_colour: vec3f::Vec3f32,
+    clearcoat_alpha: f32,
+    weights: [f32; 4],
+}
+
+impl Bsdf
+{
+    pub fn new(parameters: &Principled, base: vec3f::Vec3f32, roughness: f32, eta: f32) -> Bsdf
+    {
+        let white = vec3f::Vec3f32::new_from_points(1.0, 1.0, 1.0);
+        let brightness = luminance(&base);
+        let tint = if brightness > 0.0 { base / brightness } else { white };
+        let ggx = microfacet::Ggx::from_roughness(roughness);
+        let dielectric_specular = lerp(&white, &tint, parameters.specular_tint)
+            * (0.08 * parameters.specular);
+        let glass = (1.0 - parameters.metallic) * parameters.transmission;
+        Bsdf
+        {
+            parameters: *parameters,
+            base,
+            tint,
+            roughness,
+            ggx,
+            glass: microfacet::RoughDielectric { ggx, eta },
+            specular_colour: lerp(&dielectric_specular, &base, parameters.metallic),
+            clearcoat_alpha: 0.1 + (0.001 - 0.1) * parameters.clearcoat_gloss,
+            weights: [(1.0 - parameters.metallic) * (1.0 - parameters.transmission),
+                      1.0 - glass,
+                      0.25 * parameters.clearcoat,
+                      glass],
+        }
+    }
+
+    // Whether light gets inside, in which case the frame has to keep z
+    // pointing out of the object rather than towards wo
+    pub fn transmits(&self) -> bool
+    {
+        self.weights[GLASS] > 0.0
+    }
+
+    // How much each lobe contributes for light leaving towards wo. From
+    // inside the object only the glass interface is there.
+    fn lobe_weights(&self, wo: &vec3f::Vec3f32) -> [f32; 4]
+    {
+        if wo.z > 0.0
+        {
+            self.weights
+        }
+        else if self.transmits()
+        {
+            [0.0, 0.0, 0.0, 1.0]
+        }
+        else
+        {
+            [0.0; 4]
+        }
+    }
+
+    // Chance of sampling each lobe, in proportion to its weight
+    fn probabilities(&self, wo: &vec3f::Vec3f32) -> [f32; 4]
+    {
+        let weights = self.lobe_weights(wo);
+        let total: f32 = weights.iter().sum();
+        if total > 0.0
+        {
+            weights.map(|weight| weight / total)
+        }
+        else
+        {
+            weights
+        }
+    }
+
+    // Burley's diffuse with retro-reflection, blended towards Hanrahan and
+    // Krueger's subsurface approximation, plus sheen
+    fn diffuse(&self, wo: &vec3f::Vec3f32, wi: &vec3f::Vec3f32, cos_d: f32) -> vec3f::Vec3f32
+    {
+        let fl = schlick_weight(wi.z);
+        let fv = schlick_weight(wo.z);
+        let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+        let fd = (1.0 + (fd90 - 1.0) * fl) * (1.0 + (fd90 - 1.0) * fv);
+        let fss90 = self.roughness * cos_d * cos_d;
+        let fss = (1.0 + (fss90 - 1.0) * fl) * (1.0 + (fss90 - 1.0) * fv);
+        let ss = 1.25 * (fss * (1.0 / (wi.z + wo.z) - 0.5) + 0.5);
+        let diffuse = fd + (ss - fd) * self.parameters.subsurface;
+        let white = vec3f::Vec3f32::new_from_points(1.0, 1.0, 1.0);
+        let sheen = lerp(&white, &self.tint, self.parameters.sheen_tint)
+            * (self.parameters.sheen * schlick_weight(cos_d));
+        self.base * (diffuse * std::f32::consts::FRAC_1_PI) + sheen
+    }
+
+    fn specular(&self, wo: &vec3f::Vec3f32, wi: &vec3f::Vec3f32, h: &vec3f::Vec3f32)
+                -> vec3f::Vec3f32
+    {
+        let fresnel = optics::schlick_tinted(wo.dot_product(h), &self.specular_colour);
+        fresnel * (self.ggx.distribution(h) * self.ggx.masking_shadowing(wo, wi)
+                   / (4.0 * wo.z * wi.z))
+    }
+
+    fn clearcoat(&self, wo: &vec3f::Vec3f32, wi: &vec3f::Vec3f32, h: &vec3f::Vec3f32) -> f32
+    {
+        // Fixed refractive index of 1.5 and roughness of 0.25
+        let fresnel = 0.04 + 0.96 * schlick_weight(wo.dot_product(h));
+        let masking = microfacet::Ggx { alpha: 0.25 }.masking_shadowing(wo, wi);
+        gtr1(h.z, self.clearcoat_alpha) * fresnel * masking / (4.0 * wo.z * wi.z)
+    }
+
+    // Light passing through the glass picks up the base colour, half on the
+    // way in and half on the way out
+    fn glass_colour(&self, wo: &vec3f::Vec3f32, wi: &vec3f::Vec3f32) -> vec3f::Vec3f32
+    {
+        if wo.z * wi.z < 0.0
+        {
+            vec3f::Vec3f32::new_from_points(self.base.x.sqrt(), self.base.y.sqrt(),
+                                            self.base.z.sqrt())
+        }
+        else
+        {
+            vec3f::Vec3f32::new_from_points(1.0, 1.0, 1.0)
+        }
+    }
+
+    // BSDF value for light arriving from wi and leaving towards wo
+    pub fn evaluate(&self, wo: &vec3f::Vec3f32, wi: &vec3f::Vec3f32) -> vec3f::Vec3f32
+    {
+        let weights = self.lobe_weights(wo);
+        let mut f = vec3f::Vec3f32::zeroes();
+        if wo.z > 0.0 && wi.z > 0.0
+        {
+            let h = (*wo + *wi).unit_vector();
+            if weights[DIFFUSE] > 0.0
+            {
+                f += self.diffuse(wo, wi, wi.dot_product(&h)) * weights[DIFFUSE];
+            }
+            if weights[SPECULAR] > 0.0
+            {
+                f += self.specular(wo, wi, &h) * weights[SPECULAR];
+            }
+            if weights[CLEARCOAT] > 0.0
+            {
+                let clearcoat = self.clearcoat(wo, wi, &h) * weights[CLEARCOAT];
+                f += vec3f::Vec3f32::new_from_points(clearcoat, clearcoat, clearcoat);
+            }
+        }
+        if weights[GLASS] > 0.0
+        {
+            f += self.glass_colour(wo, wi) * (self.glass.evaluate(wo, wi) * weights[GLASS]);
+        }
+        f
+    }
+
+    // Density of `sample` returning wi, over all the lobes it could have
+    // picked
+    pub fn pdf(&self, wo: &vec3f::Vec3f32, wi: &vec3f::Vec3f32) -> f32
+    {
+        let probabilities = self.probabilities(wo);
+        let mut pdf = 0.0;
+        if wo.z > 0.0 && wi.z > 0.0
+        {
+            let h = (*wo + *wi).unit_vector();
+            let wo_h = wo.dot_product(&h);
+            pdf += probabilities[DIFFUSE] * wi.z * std::f32::consts::FRAC_1_PI;
+            pdf += probabilities[SPECULAR] * self.ggx.visible_normal_pdf(wo, &h) / (4.0 * wo_h);
+            pdf += probabilities[CLEARCOAT] * gtr1(h.z, self.clearcoat_alpha) * h.z
+                / (4.0 * wo_h);
+        }
+        if probabilities[GLASS] > 0.0
+        {
+            pdf += probabilities[GLASS] * self.glass.pdf(wo, wi);
+        }
+        pdf
+    }
+
+    // Picks a lobe with u0 and samples it with the rest, weighting the
+    // result by all the lobes together
+    pub fn sample(&self, wo: &vec3f::Vec3f32, u0: f32, u1: f32, u2: f32, u3: f32)
+                  -> Option<microfacet::BsdfSample>
+    {
+        let probabilities = self.probabilities(wo);
+        let mut lobe = 0;
+        let mut cumulative = probabilities[0];
+        while lobe < GLASS && u0 >= cumulative
+        {
+            lobe += 1;
+            cumulative += probabilities[lobe];
+        }
+        if probabilities[lobe] <= 0.0
+        {
+            return None;
+        }
+
+        let wi = match lobe
+        {
+            DIFFUSE => microfacet::sample_cosine_hemisphere(u1, u2),
+            SPECULAR =>
+            {
+                let m = self.ggx.sample_visible_normal(wo, u1, u2);
+                optics::reflect(&-*wo, &m)
+            },
+            CLEARCOAT =>
+            {
+                let a2 = self.clearcoat_alpha * self.clearcoat_alpha;
+                let cos2 = ((1.0 - a2.powf(1.0 - u1)) / (1.0 - a2)).clamp(0.0, 1.0);
+                let sin = (1.0 - cos2).sqrt();
+                let phi = 2.0 * std::f32::consts::PI * u2;
+                let h = vec3f::Vec3f32::new_from_points(sin * phi.cos(), sin * phi.sin(),
+                                                        cos2.sqrt());
+                optics::reflect(&-*wo, &h)
+            },
+            _ => self.glass.sample(wo, u1, u2, u3)?.wi,
+        };
+
+        let pdf = self.pdf(wo, &wi);
+        if pdf <= 0.0
+        {
+            return None;
+        }
+        let weight = self.evaluate(wo, &wi) * (wi.z.abs() / pdf);
+        Some(microfacet::BsdfSample { wi, weight })
+    }
+}
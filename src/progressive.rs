@@ -0,0 +1,60 @@
+use std::time::{Duration, Instant};
+
+// Renders the image in passes of one sample per pixel, writing the current
+// average out every so often so it can be watched as it refines
+#[derive(Copy, Clone)]
+pub struct Progressive
+{
+    // Write a snapshot after this many passes, never when zero
+    pub snapshot_passes: u32,
+    // Write a snapshot once this many seconds have gone by since the last
+    // one, never when zero
+    pub snapshot_seconds: f32,
+    // Stop after the pass that runs past this many seconds, even if the
+    // sample target has not been reached
+    pub time_budget: Option<f32>,
+}
+
+// Keeps track of time and passes while a progressive render runs
+pub struct Schedule
+{
+    progressive: Progressive,
+    start: Instant,
+    last_snapshot: Instant,
+}
+
+impl Schedule
+{
+    pub fn start(progressive: Progressive) -> Schedule
+    {
+        let now = Instant::now();
+        Schedule
+        {
+            progressive,
+            start: now,
+            last_snapshot: now,
+        }
+    }
+
+    // Whether a snapshot should be written after `passes` passes, restarting
+    // the snapshot clock if so
+    pub fn snapshot_due(&mut self, passes: u32) -> bool
+    {
+        let by_passes = self.progressive.snapshot_passes > 0
+            && passes.is_multiple_of(self.progressive.snapshot_passes);
+        let by_time = self.progressive.snapshot_seconds > 0.0
+            && self.last_snapshot.elapsed()
+                >= Duration::from_secs_f32(self.progressive.snapshot_seconds);
+        if by_passes || by_time
+        {
+            self.last_snapshot = Instant::now();
+        }
+        by_passes || by_time
+    }
+
+    pub fn out_of_time(&self) -> bool
+    {
+        self.progressive.time_budget
+            .is_some_and(|budget| self.start.elapsed() >= Duration::from_secs_f32(budget))
+    }
+}
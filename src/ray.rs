@@ -4,7 +4,9 @@ use crate::vec3f;
 pub struct Ray
 {
     pub a: vec3f::Vec3f32,
-    pub b: vec3f::Vec3f32
+    pub b: vec3f::Vec3f32,
+    // Instant within the camera shutter interval the ray was traced at
+    pub time: f32
 }
 
 impl Ray
@@ -15,6 +17,7 @@ impl Ray
         {
             a: vec3f::Vec3f32::zeroes(),
             b: vec3f::Vec3f32::zeroes(),
+            time: 0.0,
         }
     }
 
@@ -24,6 +27,17 @@ impl Ray
         {
             a: *a,
             b: *b,
+            time: 0.0,
+        }
+    }
+
+    pub fn new_at_time(a: &vec3f::Vec3f32, b: &vec3f::Vec3f32, time: f32) -> Ray
+    {
+        Ray
+        {
+            a: *a,
+            b: *b,
+            time,
         }
     }
 
@@ -37,8 +51,45 @@ impl Ray
         self.b
     }
 
+    pub fn time(&self) -> f32
+    {
+        self.time
+    }
+
     pub fn point_at_parameter(&self, t: &f32) -> vec3f::Vec3f32
     {
         self.a + (self.b * *t)
     }
+
+    // Range of the ray parameter, clipped to [tmin, tmax], spent inside the
+    // axis aligned box spanning `low` to `high`
+    pub fn box_span(&self, low: &vec3f::Vec3f32, high: &vec3f::Vec3f32,
+                    tmin: f32, tmax: f32) -> Option<(f32, f32)>
+    {
+        let mut t_enter = tmin;
+        let mut t_exit = tmax;
+        let axes = [(self.a.x, self.b.x, low.x, high.x),
+                    (self.a.y, self.b.y, low.y, high.y),
+                    (self.a.z, self.b.z, low.z, high.z)];
+        for &(origin, direction, low, high) in &axes
+        {
+            if direction == 0.0
+            {
+                if origin < low || origin > high
+                {
+                    return None;
+                }
+                continue;
+            }
+            let t0 = (low - origin) / direction;
+            let t1 = (high - origin) / direction;
+            t_enter = t_enter.max(t0.min(t1));
+            t_exit = t_exit.min(t0.max(t1));
+        }
+        if t_enter > t_exit
+        {
+            return None;
+        }
+        Some((t_enter, t_exit))
+    }
 }
@@ -0,0 +1,35 @@
+use std::cell::RefCell;
+
+use crate::sampler;
+
+// Every random number the renderer draws comes from the sampler installed
+// for the current thread, so that the camera and the materials do not need
+// to be handed one
+thread_local!
+{
+    static SAMPLER: RefCell<Box<dyn sampler::Sampler>> =
+        RefCell::new(Box::new(sampler::Independent::new(rand::random())));
+}
+
+// The next dimension of the current sample
+pub fn random() -> f32
+{
+    SAMPLER.with(|sampler| sampler.borrow_mut().next_1d())
+}
+
+pub fn set_sampler(sampler: Box<dyn sampler::Sampler>)
+{
+    SAMPLER.with(|current| *current.borrow_mut() = sampler);
+}
+
+pub fn start_sample(x: u32, y: u32, index: u32)
+{
+    SAMPLER.with(|sampler| sampler.borrow_mut().start_sample(x, y, index));
+}
+
+// Switches to a plain stream of independent numbers that comes out the same
+// for the same seed, for work outside of rendering samples
+pub fn reseed(seed: u32)
+{
+    set_sampler(Box::new(sampler::Independent::new(seed)));
+}
@@ -0,0 +1,389 @@
+use crate::bluenoise;
+
+// Sources of the numbers in [0, 1) a camera sample is built from. Every
+// draw takes the next dimension of the current pixel sample, so the first
+// two always jitter the pixel and the ones after follow the path in the order
+// the camera and the materials ask for them.
+pub trait Sampler
+{
+    // Starts sample `index` of pixel (x, y), counted from the top left of
+    // the image
+    fn start_sample(&mut self, x: u32, y: u32, index: u32);
+
+    fn next_1d(&mut self) -> f32;
+}
+
+#[derive(Copy, Clone)]
+pub enum SamplerKind
+{
+    Independent,
+    // Each dimension split into as many strata as there are samples per
+    // pixel, with the strata visited in a different random order per
+    // dimension
+    Stratified,
+    // Radical inverses in the first prime bases, randomised per pixel with
+    // a Cranley-Patterson rotation
+    Halton,
+    // Sobol points in padded groups of four dimensions, with hash-based
+    // Owen scrambling and shuffling (Burley 2020)
+    Sobol,
+    // Rank-1 lattice shifted per pixel by a blue noise mask, so that at a
+    // few samples per pixel the error is spread as blue noise across the
+    // image instead of as white noise (Georgiev and Fajardo 2016)
+    BlueNoise,
+}
+
+impl SamplerKind
+{
+    pub fn build(&self, seed: u64, samples_per_pixel: u32) -> Box<dyn Sampler>
+    {
+        let seed = (seed ^ (seed >> 32)) as u32;
+        match *self
+        {
+            SamplerKind::Independent => Box::new(Independent::new(seed)),
+            SamplerKind::Stratified => Box::new(Stratified
+            {
+                state: State::new(seed),
+                strata: samples_per_pixel.max(1),
+            }),
+            SamplerKind::Halton => Box::new(Halton { state: State::new(seed) }),
+            SamplerKind::Sobol => Box::new(Sobol
+            {
+                state: State::new(seed),
+                directions: sobol_directions(),
+            }),
+            SamplerKind::BlueNoise => Box::new(BlueNoise
+            {
+                state: State::new(seed),
+                mask: bluenoise::mask(),
+            }),
+        }
+    }
+}
+
+// Integer hash with good avalanche (Chris Wellons' lowbias32)
+fn mix(mut x: u32) -> u32
+{
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb_352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846c_a68b);
+    x ^= x >> 16;
+    x
+}
+
+fn hash(a: u32, b: u32) -> u32
+{
+    mix(a ^ mix(b).wrapping_add(0x9e37_79b9))
+}
+
+fn to_unit(x: u32) -> f32
+{
+    // 24 bits so that the result is always below one
+    (x >> 8) as f32 * (1.0 / (1u32 << 24) as f32)
+}
+
+// Where in the sample space a sampler currently is
+struct State
+{
+    seed: u32,
+    x: u32,
+    y: u32,
+    pixel: u32,
+    index: u32,
+    dimension: u32,
+}
+
+impl State
+{
+    fn new(seed: u32) -> State
+    {
+        State
+        {
+            seed,
+            x: 0,
+            y: 0,
+            pixel: 0,
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn start(&mut self, x: u32, y: u32, index: u32)
+    {
+        self.x = x;
+        self.y = y;
+        self.pixel = hash(x, y);
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    // Takes the next dimension, returning it along with a seed unique to it
+    // and the pixel
+    fn next(&mut self) -> (u32, u32)
+    {
+        let dimension = self.dimension;
+        self.dimension += 1;
+        (dimension, hash(hash(self.seed, self.pixel), dimension))
+    }
+
+    // A value unrelated to every other sample and dimension
+    fn independent(&self, dimension_seed: u32) -> f32
+    {
+        to_unit(hash(dimension_seed, self.index))
+    }
+}
+
+pub struct Independent
+{
+    state: State,
+}
+
+impl Independent
+{
+    pub fn new(seed: u32) -> Independent
+    {
+        Independent { state: State::new(seed) }
+    }
+}
+
+impl Sampler for Independent
+{
+    fn start_sample(&mut self, x: u32, y: u32, index: u32)
+    {
+        self.state.start(x, y, index);
+    }
+
+    fn next_1d(&mut self) -> f32
+    {
+        let (_, seed) = self.state.next();
+        self.state.independent(seed)
+    }
+}
+
+// Andrew Kensler's hash-based permutation of [0, length)
+fn permute(mut i: u32, length: u32, p: u32) -> u32
+{
+    let mut w = length - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop
+    {
+        i ^= p;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < length
+        {
+            break;
+        }
+    }
+    (i.wrapping_add(p)) % length
+}
+
+pub struct Stratified
+{
+    state: State,
+    strata: u32,
+}
+
+impl Sampler for Stratified
+{
+    fn start_sample(&mut self, x: u32, y: u32, index: u32)
+    {
+        self.state.start(x, y, index);
+    }
+
+    fn next_1d(&mut self) -> f32
+    {
+        let (_, seed) = self.state.next();
+        // Samples past the stratum count, which adaptive sampling can take,
+        // start another round in a fresh order
+        let round = self.state.index / self.strata;
+        let stratum = permute(self.state.index % self.strata, self.strata, hash(seed, round));
+        let jitter = self.state.independent(seed);
+        ((stratum as f32 + jitter) / self.strata as f32).min(1.0 - f32::EPSILON)
+    }
+}
+
+const PRIMES: [u32; 32] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+                           59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131];
+
+fn radical_inverse(base: u32, mut index: u32) -> f32
+{
+    let inverse_base = 1.0 / base as f64;
+    let mut scale = inverse_base;
+    let mut result = 0.0;
+    while index > 0
+    {
+        result += (index % base) as f64 * scale;
+        index /= base;
+        scale *= inverse_base;
+    }
+    result as f32
+}
+
+pub struct Halton
+{
+    state: State,
+}
+
+impl Sampler for Halton
+{
+    fn start_sample(&mut self, x: u32, y: u32, index: u32)
+    {
+        self.state.start(x, y, index);
+    }
+
+    fn next_1d(&mut self) -> f32
+    {
+        let (dimension, seed) = self.state.next();
+        match PRIMES.get(dimension as usize)
+        {
+            Some(&base) =>
+            {
+                let rotated = radical_inverse(base, self.state.index) + to_unit(seed);
+                let wrapped = rotated - rotated.floor();
+                wrapped.min(1.0 - f32::EPSILON)
+            },
+            // Far enough down the path that stratification no longer pays
+            None => self.state.independent(seed),
+        }
+    }
+}
+
+// Direction numbers of the first four Sobol dimensions, from the primitive
+// polynomials and initial values of Joe and Kuo
+fn sobol_directions() -> [[u32; 32]; 4]
+{
+    // Degree, polynomial coefficients between the leading and trailing
+    // terms, and the initial m values
+    const POLYNOMIALS: [(usize, u32, [u32; 3]); 3] = [(1, 0, [1, 0, 0]),
+                                                      (2, 1, [1, 3, 0]),
+                                                      (3, 1, [1, 3, 1])];
+    let mut directions = [[0; 32]; 4];
+    for (bit, direction) in directions[0].iter_mut().enumerate()
+    {
+        *direction = 1 << (31 - bit);
+    }
+    for (dimension, &(degree, coefficients, initial)) in POLYNOMIALS.iter().enumerate()
+    {
+        let mut m = [0u32; 32];
+        m[.. degree].copy_from_slice(&initial[.. degree]);
+        for k in degree .. 32
+        {
+            let mut value = m[k - degree] ^ (m[k - degree] << degree);
+            for j in 1 .. degree
+            {
+                if (coefficients >> (degree - 1 - j)) & 1 == 1
+                {
+                    value ^= m[k - j] << j;
+                }
+            }
+            m[k] = value;
+        }
+        for (bit, direction) in directions[dimension + 1].iter_mut().enumerate()
+        {
+            *direction = m[bit] << (31 - bit);
+        }
+    }
+    directions
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32
+{
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32
+{
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+pub struct Sobol
+{
+    state: State,
+    directions: [[u32; 32]; 4],
+}
+
+impl Sampler for Sobol
+{
+    fn start_sample(&mut self, x: u32, y: u32, index: u32)
+    {
+        self.state.start(x, y, index);
+    }
+
+    fn next_1d(&mut self) -> f32
+    {
+        let (dimension, seed) = self.state.next();
+        // Every group of four dimensions walks the points in its own
+        // shuffled order, so the groups are not correlated with each other
+        let group = dimension / 4;
+        let group_seed = hash(hash(self.state.seed, self.state.pixel), group | 0x8000_0000);
+        let index = nested_uniform_scramble(self.state.index, group_seed);
+        let mut x = 0;
+        for (bit, direction) in self.directions[(dimension % 4) as usize].iter().enumerate()
+        {
+            if (index >> bit) & 1 == 1
+            {
+                x ^= direction;
+            }
+        }
+        to_unit(nested_uniform_scramble(x, seed))
+    }
+}
+
+pub struct BlueNoise
+{
+    state: State,
+    mask: &'static [f32],
+}
+
+impl Sampler for BlueNoise
+{
+    fn start_sample(&mut self, x: u32, y: u32, index: u32)
+    {
+        self.state.start(x, y, index);
+    }
+
+    fn next_1d(&mut self) -> f32
+    {
+        // Roberts' R2 sequence, the two dimensional golden ratio lattice,
+        // for each pair of dimensions
+        const PLASTIC: f64 = 1.324_717_957_244_746;
+
+        let (dimension, _) = self.state.next();
+        // Every dimension reads the mask through its own wrap-around shift,
+        // which keeps the dimensions apart while each stays blue
+        let shift = hash(self.state.seed, dimension);
+        let size = bluenoise::SIZE as u32;
+        let mx = (self.state.x + (shift & 0xffff)) % size;
+        let my = (self.state.y + (shift >> 16)) % size;
+        let offset = self.mask[(my * size + mx) as usize] as f64;
+        let alpha = if dimension % 2 == 0 { 1.0 / PLASTIC } else { 1.0 / (PLASTIC * PLASTIC) };
+        let value = offset + self.state.index as f64 * alpha;
+        ((value - value.floor()) as f32).min(1.0 - f32::EPSILON)
+    }
+}
@@ -0,0 +1,148 @@
+use crate::vec3f;
+use crate::ray;
+use crate::camera;
+use crate::camera::Camera;
+use crate::checkpoint;
+
+#[derive(Copy, Clone)]
+pub enum Eye
+{
+    Left,
+    Right,
+}
+
+impl Eye
+{
+    // Which way along the camera's right vector the eye sits
+    fn side(&self) -> f32
+    {
+        match *self
+        {
+            Eye::Left => -1.0,
+            Eye::Right => 1.0,
+        }
+    }
+}
+
+// A pair of cameras built from one camera description, spaced
+// `interocular` world units apart across the view direction
+#[derive(Copy, Clone)]
+pub struct StereoRig
+{
+    pub interocular: f32,
+    // Distance in front of the rig at which the two eyes see the same
+    // point in the same place, so that it appears at screen depth. Zero
+    // puts it at infinity.
+    pub convergence: f32,
+}
+
+impl StereoRig
+{
+    // Builds the camera for one eye. Equirectangular descriptions produce
+    // omni-directional stereo panoramas. The eyes of every other projection
+    // look parallel to each other, and perspective eyes shift their frustums
+    // towards each other so that they line up at the convergence distance
+    // without the keystone distortion of toeing in. Fails when the
+    // description cannot be built.
+    pub fn eye_camera(&self, description: &camera::CameraDescription,
+                      eye: Eye) -> Result<Box<dyn Camera>, String>
+    {
+        if let camera::Projection::Equirectangular = description.projection
+        {
+            return Ok(Box::new(OdsCamera::new(description.look_from, description.look_at,
+                                              description.vup, self.interocular, eye)
+                               .with_shutter(description.shutter.open,
+                                             description.shutter.close)));
+        }
+
+        let (u, _, _) = camera::camera_frame(description.look_from,
+                                             description.look_at,
+                                             description.vup);
+        let half_separation = eye.side() * self.interocular / 2.0;
+        let offset = u * half_separation;
+        let mut eye_description = description.clone();
+        eye_description.look_from = description.look_from + offset;
+        eye_description.look_at = description.look_at + offset;
+        match description.projection
+        {
+            camera::Projection::Perspective if self.convergence > 0.0 =>
+            {
+                // At the convergence distance the window is as wide as
+                // 2 tan(fov / 2) aspect times that distance, and has to
+                // move back by the eye's offset to sit centred in front of
+                // the rig
+                let half_width = description.aspect
+                    * (description.fov.to_radians() / 2.0).tan();
+                let shift = -half_separation / (2.0 * half_width * self.convergence);
+                let d = &eye_description;
+                Ok(Box::new(camera::ThinLensCamera::new(d.look_from, d.look_at, d.vup,
+                                                        d.fov, d.aspect, d.aperature,
+                                                        d.focus_dist)
+                            .with_lens_effects(d.lens.clone())
+                            .with_shutter(d.shutter.open, d.shutter.close)
+                            .with_horizontal_shift(shift)))
+            },
+            _ => eye_description.build(),
+        }
+    }
+
+    pub fn fingerprint(&self, fingerprint: &mut checkpoint::Fingerprint)
+    {
+        fingerprint.f32(self.interocular);
+        fingerprint.f32(self.convergence);
+    }
+}
+
+// Omni-directional stereo panorama for one eye: an equirectangular
+// panorama whose rays start on a circle of diameter `interocular` around
+// the centre, each offset sideways from its own viewing direction so that
+// every direction is seen with correct parallax
+pub struct OdsCamera
+{
+    origin: vec3f::Vec3f32,
+    u: vec3f::Vec3f32,
+    v: vec3f::Vec3f32,
+    w: vec3f::Vec3f32,
+    eye_offset: f32,
+    shutter: camera::Shutter,
+}
+
+impl OdsCamera
+{
+    pub fn new(look_from: vec3f::Vec3f32, look_at: vec3f::Vec3f32,
+               vup: vec3f::Vec3f32, interocular: f32, eye: Eye) -> OdsCamera
+    {
+        let (u, v, w) = camera::camera_frame(look_from, look_at, vup);
+        OdsCamera
+        {
+            origin: look_from,
+            u,
+            v,
+            w,
+            eye_offset: eye.side() * interocular / 2.0,
+            shutter: camera::Shutter::instant(),
+        }
+    }
+
+    pub fn with_shutter(mut self, time0: f32, time1: f32) -> OdsCamera
+    {
+        self.shutter = camera::Shutter { open: time0, close: time1 };
+        self
+    }
+}
+
+impl Camera for OdsCamera
+{
+    fn get_ray(&self, u: f32, v: f32) -> Option<ray::Ray>
+    {
+        let longitude = (u - 0.5) * 2.0 * std::f32::consts::PI;
+        let latitude = (v - 0.5) * std::f32::consts::PI;
+        let forward = self.u * longitude.sin() - self.w * longitude.cos();
+        let right = self.u * longitude.cos() + self.w * longitude.sin();
+        let direction = forward * latitude.cos() + self.v * latitude.sin();
+        // Shrinking the baseline towards the poles hides the seam where the
+        // eyes would otherwise swap over
+        let origin = self.origin + right * (self.eye_offset * latitude.cos());
+        Some(ray::Ray::new_at_time(&origin, &direction, self.shutter.sample()))
+    }
+}
@@ -0,0 +1,162 @@
+use crate::vec3f;
+use crate::ray;
+use crate::rng;
+use crate::volume;
+use crate::microfacet;
+use crate::checkpoint;
+use crate::hittable::{HitRecord, Hittable};
+
+// Interactions a walk goes through inside before it is given up on
+const MAX_STEPS: u32 = 256;
+
+// Closer than this to where a step starts, a hit on the boundary is taken to
+// be the point the step left from
+const BOUNDARY_EPSILON: f32 = 1e-4;
+
+// Light entering a closed object through its rough dielectric boundary and
+// scattering around inside it before coming back out, as in skin, wax and
+// marble. The albedo of the surface is the colour the object ends up with
+// once all that scattering is done, rather than the albedo of each
+// interaction.
+#[derive(Copy, Clone)]
+pub struct Subsurface
+{
+    // Average distance light travels between interactions, per channel
+    pub mean_free_path: vec3f::Vec3f32,
+    pub phase: volume::PhaseFunction,
+}
+
+// Where a walk left the object and the throughput it carried
+pub struct Exit
+{
+    pub p: vec3f::Vec3f32,
+    pub direction: vec3f::Vec3f32,
+    pub weight: vec3f::Vec3f32,
+}
+
+// Chiang et al.'s fit from the albedo a surface appears to have to the
+// single scattering albedo giving it in a random walk
+fn single_scattering_albedo(albedo: f32) -> f32
+{
+    let a = albedo.clamp(0.0, 1.0);
+    let t = 4.09712 + 4.20863 * a - (9.59217 + 41.6808 * a + 17.7126 * a * a).sqrt();
+    (1.0 - t * t).clamp(0.0, 1.0)
+}
+
+fn transmittance(extinction: &vec3f::Vec3f32, distance: f32) -> vec3f::Vec3f32
+{
+    vec3f::Vec3f32::new_from_points((-extinction.x * distance).exp(),
+                                    (-extinction.y * distance).exp(),
+                                    (-extinction.z * distance).exp())
+}
+
+fn average(v: &vec3f::Vec3f32) -> f32
+{
+    (v.x + v.y + v.z) / 3.0
+}
+
+impl Subsurface
+{
+    // Measured by Jensen et al. (2001), for scenes with `units_per_mm` units
+    // to the millimetre
+    pub fn skin(units_per_mm: f32) -> Subsurface
+    {
+        Subsurface
+        {
+            mean_free_path: vec3f::Vec3f32::new_from_points(1.295, 0.952, 0.671) * units_per_mm,
+            phase: volume::PhaseFunction::Isotropic,
+        }
+    }
+
+    pub fn marble(units_per_mm: f32) -> Subsurface
+    {
+        Subsurface
+        {
+            mean_free_path: vec3f::Vec3f32::new_from_points(0.456, 0.381, 0.333) * units_per_mm,
+            phase: volume::PhaseFunction::Isotropic,
+        }
+    }
+
+    // Follows light arriving along `direction` at `rec` on `object` until
+    // it leaves the object again, either straight off the boundary or after
+    // wandering inside. The object has to be closed with its normals facing
+    // out, and anything else inside it is ignored.
+    pub fn sample(&self, object: &dyn Hittable, rec: &HitRecord, direction: &vec3f::Vec3f32,
+                  time: f32) -> Option<Exit>
+    {
+        let surface = object.surface();
+        let boundary = microfacet::RoughDielectric
+        {
+            ggx: microfacet::Ggx::from_roughness(surface.fuzz),
+            eta: surface.refraction,
+        };
+        let frame = microfacet::Frame::new(&rec.normal);
+        let wo = frame.to_local(&-*direction);
+        if wo.z <= 0.0
+        {
+            return None;
+        }
+        let entry = boundary.sample(&wo, rng::random(), rng::random(), rng::random())?;
+        let mut weight = entry.weight;
+        if entry.wi.z > 0.0
+        {
+            return Some(Exit { p: rec.p, direction: frame.to_world(&entry.wi), weight });
+        }
+
+        let extinction = vec3f::Vec3f32::new_from_points(1.0 / self.mean_free_path.x,
+                                                         1.0 / self.mean_free_path.y,
+                                                         1.0 / self.mean_free_path.z);
+        let scattering = vec3f::Vec3f32::new_from_points(
+            single_scattering_albedo(surface.albedo.x) * extinction.x,
+            single_scattering_albedo(surface.albedo.y) * extinction.y,
+            single_scattering_albedo(surface.albedo.z) * extinction.z);
+        let mut p = rec.p;
+        let mut w = frame.to_world(&entry.wi);
+        for _ in 0 .. MAX_STEPS
+        {
+            // Distances are sampled in one channel picked at random and
+            // weighted by the average density of all three, so that none of
+            // them blows up where the others are far denser
+            let channel = ((rng::random() * 3.0) as usize).min(2);
+            let density = [extinction.x, extinction.y, extinction.z][channel];
+            let distance = -(1.0 - rng::random()).ln() / density;
+
+            let mut step = ray::Ray::new_at_time(&p, &w, time);
+            let mut hit = HitRecord::zeroes();
+            if !object.hit(&mut step, BOUNDARY_EPSILON, f32::MAX, &mut hit)
+            {
+                // Numerically outside already
+                return None;
+            }
+
+            if distance < hit.t
+            {
+                let survived = transmittance(&extinction, distance);
+                weight *= scattering * survived / average(&(extinction * survived));
+                p += w * distance;
+                w = self.phase.sample(&w);
+                continue;
+            }
+
+            let survived = transmittance(&extinction, hit.t);
+            weight *= survived / average(&survived);
+            p = hit.p;
+            let frame = microfacet::Frame::new(&hit.normal);
+            let wo = frame.to_local(&-w);
+            let interface = boundary.sample(&wo, rng::random(), rng::random(), rng::random())?;
+            weight *= interface.weight;
+            if interface.wi.z > 0.0
+            {
+                return Some(Exit { p, direction: frame.to_world(&interface.wi), weight });
+            }
+            w = frame.to_world(&interface.wi);
+        }
+        None
+    }
+
+    pub fn fingerprint(&self, fingerprint: &mut checkpoint::Fingerprint)
+    {
+        fingerprint.vec3(&self.mean_free_path);
+        self.phase.fingerprint(fingerprint);
+    }
+}
@@ -0,0 +1,129 @@
+use crate::vec3f;
+
+#[derive(Copy, Clone)]
+pub enum ToneMapper
+{
+    // Cuts everything above 1 off
+    Clamp,
+    // x / (1 + x) on every channel
+    Reinhard,
+    // Krzysztof Narkowicz's fit of the ACES reference rendering transform
+    AcesFilmic,
+    // Troy Sobotka's AgX with the default contrast look, which desaturates
+    // bright colours towards white instead of skewing their hue
+    Agx,
+}
+
+// Turns accumulated scene radiance into display values: exposure and white
+// balance first, then tone mapping and finally the sRGB transfer function
+#[derive(Copy, Clone)]
+pub struct OutputTransform
+{
+    // In stops, each one doubles the brightness
+    pub exposure: f32,
+    // Colour of something neutral under the scene lighting, which is
+    // mapped back to grey
+    pub white: vec3f::Vec3f32,
+    pub tone_mapper: ToneMapper,
+}
+
+fn luminance(c: &vec3f::Vec3f32) -> f32
+{
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
+
+fn multiply_rows(rows: &[[f32; 3]; 3], c: &vec3f::Vec3f32) -> vec3f::Vec3f32
+{
+    vec3f::Vec3f32::new_from_points(rows[0][0] * c.x + rows[0][1] * c.y + rows[0][2] * c.z,
+                                    rows[1][0] * c.x + rows[1][1] * c.y + rows[1][2] * c.z,
+                                    rows[2][0] * c.x + rows[2][1] * c.y + rows[2][2] * c.z)
+}
+
+fn map_channels(c: &vec3f::Vec3f32, f: impl Fn(f32) -> f32) -> vec3f::Vec3f32
+{
+    vec3f::Vec3f32::new_from_points(f(c.x), f(c.y), f(c.z))
+}
+
+fn aces_filmic(x: f32) -> f32
+{
+    // The fit expects the exposure of the ACES reference, which is 0.6 of
+    // ours for the same look
+    let x = x * 0.6;
+    ((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)).clamp(0.0, 1.0)
+}
+
+fn agx(c: &vec3f::Vec3f32) -> vec3f::Vec3f32
+{
+    const INSET: [[f32; 3]; 3] = [[0.842_479_06, 0.078_433_6, 0.079_223_745],
+                                  [0.042_328_24, 0.878_468_6, 0.079_166_13],
+                                  [0.042_375_655, 0.078_433_6, 0.879_143]];
+    const OUTSET: [[f32; 3]; 3] = [[1.196_879, -0.098_020_88, -0.099_029_74],
+                                   [-0.052_896_85, 1.151_903_1, -0.098_961_18],
+                                   [-0.052_971_635, -0.098_043_45, 1.151_073_7]];
+    const MIN_EV: f32 = -12.473_93;
+    const MAX_EV: f32 = 4.026_069;
+
+    // Log encode the inset colour and push it through the polynomial fit of
+    // the default contrast curve
+    let inset = multiply_rows(&INSET, c);
+    let curve = map_channels(&inset, |x|
+    {
+        let x = (x.max(1e-10).log2().clamp(MIN_EV, MAX_EV) - MIN_EV) / (MAX_EV - MIN_EV);
+        let x2 = x * x;
+        let x4 = x2 * x2;
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x
+            + 0.4298 * x2 + 0.1191 * x - 0.00232
+    });
+    // The curve produces display encoded values, undo the 2.2 gamma so
+    // every tone mapper hands linear values to the sRGB encoding
+    map_channels(&multiply_rows(&OUTSET, &curve), |x| x.clamp(0.0, 1.0).powf(2.2))
+}
+
+// The exact piecewise sRGB transfer function
+fn srgb_encode(x: f32) -> f32
+{
+    if x <= 0.003_130_8
+    {
+        12.92 * x
+    }
+    else
+    {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+impl OutputTransform
+{
+    pub fn standard() -> OutputTransform
+    {
+        OutputTransform
+        {
+            exposure: 0.0,
+            white: vec3f::Vec3f32::new_from_points(1.0, 1.0, 1.0),
+            tone_mapper: ToneMapper::Clamp,
+        }
+    }
+
+    // Display encoded colour with every channel in [0, 1]
+    pub fn apply(&self, radiance: &vec3f::Vec3f32) -> vec3f::Vec3f32
+    {
+        let white_balance = vec3f::Vec3f32::new_from_points(1.0 / self.white.x,
+                                                            1.0 / self.white.y,
+                                                            1.0 / self.white.z)
+            * luminance(&self.white);
+        let exposed = map_channels(&(*radiance * white_balance), |x|
+        {
+            // NaNs from degenerate paths would otherwise poison the mapping
+            if x.is_nan() { 0.0 } else { x.max(0.0) }
+        }) * 2f32.powf(self.exposure);
+
+        let mapped = match self.tone_mapper
+        {
+            ToneMapper::Clamp => map_channels(&exposed, |x| x.min(1.0)),
+            ToneMapper::Reinhard => map_channels(&exposed, |x| x / (1.0 + x)),
+            ToneMapper::AcesFilmic => map_channels(&exposed, aces_filmic),
+            ToneMapper::Agx => agx(&exposed),
+        };
+        map_channels(&mapped, |x| srgb_encode(x.clamp(0.0, 1.0)))
+    }
+}
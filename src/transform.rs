@@ -0,0 +1,125 @@
+use crate::vec3f;
+use crate::ray;
+use crate::mat;
+use crate::animation;
+use crate::checkpoint;
+use crate::hittable::{HitRecord, Hittable, Surface};
+
+// Placement of an object: scaled first, then rotated about the x, y and z
+// axes in that order (angles in degrees), then translated
+#[derive(Copy, Clone)]
+pub struct Pose
+{
+    pub translation: vec3f::Vec3f32,
+    pub rotation: vec3f::Vec3f32,
+    pub scale: vec3f::Vec3f32,
+}
+
+impl Pose
+{
+    pub fn identity() -> Pose
+    {
+        Pose
+        {
+            translation: vec3f::Vec3f32::zeroes(),
+            rotation: vec3f::Vec3f32::zeroes(),
+            scale: vec3f::Vec3f32::new_from_points(1.0, 1.0, 1.0),
+        }
+    }
+
+    pub fn matrix(&self) -> mat::Matrix44f32
+    {
+        let rotation = mat::Matrix44f32::rotation_z(self.rotation.z)
+            .product(&mat::Matrix44f32::rotation_y(self.rotation.y))
+            .product(&mat::Matrix44f32::rotation_x(self.rotation.x));
+        mat::Matrix44f32::translation(&self.translation)
+            .product(&rotation)
+            .product(&mat::Matrix44f32::scaling(&self.scale))
+    }
+
+    pub fn fingerprint(&self, fingerprint: &mut checkpoint::Fingerprint)
+    {
+        fingerprint.vec3(&self.translation);
+        fingerprint.vec3(&self.rotation);
+        fingerprint.vec3(&self.scale);
+    }
+}
+
+// Places another object in the world with a pose animated over time, sampled
+// at the ray's time so that moving objects blur across the shutter interval
+pub struct Transform
+{
+    object: Box<dyn Hittable>,
+    track: animation::Track<Pose>,
+}
+
+impl Transform
+{
+    pub fn new(object: Box<dyn Hittable>, pose: Pose) -> Transform
+    {
+        Transform
+        {
+            object,
+            track: animation::Track::constant(pose),
+        }
+    }
+
+    // Moves linearly from `start` at time0 to `end` at time1
+    pub fn animated(object: Box<dyn Hittable>, start: Pose, end: Pose,
+                    time0: f32, time1: f32) -> Transform
+    {
+        let keys = vec![animation::Keyframe { time: time0, value: start },
+                        animation::Keyframe { time: time1, value: end }];
+        Transform
+        {
+            object,
+            track: animation::Track::new(keys, animation::Interpolation::Linear),
+        }
+    }
+
+    pub fn keyframed(object: Box<dyn Hittable>,
+                     track: animation::Track<Pose>) -> Transform
+    {
+        Transform
+        {
+            object,
+            track,
+        }
+    }
+}
+
+impl Hittable for Transform
+{
+    fn hit(&self, r: &mut ray::Ray, tmin: f32, tmax: f32,
+           rec: &mut HitRecord) -> bool
+    {
+        let object_to_world = self.track.sample(r.time()).matrix();
+        let world_to_object = object_to_world.inverse();
+
+        // The transform is affine, so the ray parameter is the same in both
+        // spaces as long as the direction is not renormalised
+        let mut local = ray::Ray::new_at_time(&world_to_object.transform_point(&r.origin()),
+                                              &world_to_object.transform_vector(&r.direction()),
+                                              r.time());
+        if !self.object.hit(&mut local, tmin, tmax, rec)
+        {
+            return false;
+        }
+        rec.p = object_to_world.transform_point(&rec.p);
+        rec.normal = world_to_object.transpose()
+            .transform_vector(&rec.normal)
+            .unit_vector();
+        true
+    }
+
+    fn surface(&self) -> &Surface
+    {
+        self.object.surface()
+    }
+
+    fn fingerprint(&self, fingerprint: &mut checkpoint::Fingerprint)
+    {
+        self.object.fingerprint(fingerprint);
+        self.track.fingerprint(fingerprint, Pose::fingerprint);
+    }
+}
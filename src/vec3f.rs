@@ -58,7 +58,7 @@ impl Vec3f32
 
     pub fn normalize(&self) -> Vec3f32
     {
-        let magnitude = self.dot_product(&self).sqrt();
+        let magnitude = self.dot_product(self).sqrt();
         let mut new_x: f32 = self.x;
         let mut new_y: f32 = self.y;
         let mut new_z: f32 = self.z;
@@ -82,9 +82,26 @@ impl Vec3f32
         *self / self.length()
     }
 
+    // Two unit vectors that together with this (unit) vector form a right
+    // handed orthonormal basis
+    pub fn orthonormal_basis(&self) -> (Vec3f32, Vec3f32)
+    {
+        let helper = if self.x.abs() > 0.9
+        {
+            Vec3f32::new_from_points(0.0, 1.0, 0.0)
+        }
+        else
+        {
+            Vec3f32::new_from_points(1.0, 0.0, 0.0)
+        };
+        let tangent = helper.cross_product(self).unit_vector();
+        let bitangent = self.cross_product(&tangent);
+        (tangent, bitangent)
+    }
+
     pub fn write_vec_as_int(&self, file: &mut File)
     {
-        write!(file, "{} {} {}\n", self.x as i32, self.y as i32, self.z as i32).unwrap();
+        writeln!(file, "{} {} {}", self.x as i32, self.y as i32, self.z as i32).unwrap();
     }
 }
 
@@ -0,0 +1,216 @@
+use crate::vec3f;
+use crate::rng;
+use crate::ray;
+use crate::checkpoint;
+use crate::hittable::{HitRecord, Hittable, Material, Surface};
+
+// Angular distribution of light scattered inside a medium
+#[derive(Copy, Clone)]
+pub enum PhaseFunction
+{
+    Isotropic,
+    // Asymmetry parameter g in (-1, 1): positive values scatter forwards,
+    // negative values backwards and zero is isotropic
+    HenyeyGreenstein(f32),
+}
+
+impl PhaseFunction
+{
+    // Value of the phase function for the cosine of the angle between the
+    // propagation directions before and after scattering
+    pub fn evaluate(&self, cos_theta: f32) -> f32
+    {
+        match *self
+        {
+            PhaseFunction::Isotropic => 1.0 / (4.0 * std::f32::consts::PI),
+            PhaseFunction::HenyeyGreenstein(g) =>
+            {
+                let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+                (1.0 - g * g) / (4.0 * std::f32::consts::PI * denom * denom.sqrt())
+            }
+        }
+    }
+
+    // Picks a new unit direction for light travelling along `direction`.
+    // The sample is drawn exactly from the phase function, so the scattered
+    // path carries no extra weight.
+    pub fn sample(&self, direction: &vec3f::Vec3f32) -> vec3f::Vec3f32
+    {
+        let xi = rng::random();
+        let cos_theta = match *self
+        {
+            PhaseFunction::HenyeyGreenstein(g) if g.abs() > 1e-3 =>
+            {
+                let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * xi);
+                ((1.0 + g * g - s * s) / (2.0 * g)).clamp(-1.0, 1.0)
+            },
+            _ => 1.0 - 2.0 * xi,
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * std::f32::consts::PI * rng::random();
+        let (tangent, bitangent) = direction.orthonormal_basis();
+        tangent * (sin_theta * phi.cos()) + bitangent * (sin_theta * phi.sin())
+            + *direction * cos_theta
+    }
+
+    pub fn fingerprint(&self, fingerprint: &mut checkpoint::Fingerprint)
+    {
+        match *self
+        {
+            PhaseFunction::Isotropic => fingerprint.u32(0),
+            PhaseFunction::HenyeyGreenstein(g) =>
+            {
+                fingerprint.u32(1);
+                fingerprint.f32(g);
+            },
+        }
+    }
+}
+
+// Samples a free-flight distance through a medium of the given density,
+// converted to the ray parameter of a ray whose direction has `length`
+fn sample_free_flight(density: f32, length: f32) -> f32
+{
+    -(1.0 - rng::random()).ln() / (density * length)
+}
+
+// A medium of constant density filling the inside of a closed object.
+// Hits are scattering events inside the medium rather than surfaces.
+pub struct ConstantMedium
+{
+    boundary: Box<dyn Hittable>,
+    density: f32,
+    surface: Surface,
+}
+
+impl ConstantMedium
+{
+    pub fn new(boundary: Box<dyn Hittable>, density: f32,
+               albedo: vec3f::Vec3f32, phase: PhaseFunction) -> ConstantMedium
+    {
+        ConstantMedium
+        {
+            boundary,
+            density,
+            surface: Surface
+            {
+                material: Material::Medium(phase),
+                albedo,
+                fuzz: 0.0,
+                refraction: 1.0,
+            },
+        }
+    }
+}
+
+impl Hittable for ConstantMedium
+{
+    fn hit(&self, r: &mut ray::Ray, tmin: f32, tmax: f32,
+           rec: &mut HitRecord) -> bool
+    {
+        // Find where the ray line enters and leaves the boundary, then keep
+        // the part of that span between tmin and tmax
+        let mut entry = HitRecord::zeroes();
+        let mut exit = HitRecord::zeroes();
+        if !self.boundary.hit(r, -f32::MAX, f32::MAX, &mut entry)
+        {
+            return false;
+        }
+        if !self.boundary.hit(r, entry.t + 0.0001, f32::MAX, &mut exit)
+        {
+            return false;
+        }
+        let start = entry.t.max(tmin);
+        let end = exit.t.min(tmax);
+        if start >= end
+        {
+            return false;
+        }
+
+        let t = start + sample_free_flight(self.density, r.direction().length());
+        if t >= end
+        {
+            return false;
+        }
+        rec.t = t;
+        rec.p = r.point_at_parameter(&t);
+        // There is no surface at a scattering event, the normal is arbitrary
+        rec.normal = vec3f::Vec3f32::new_from_points(1.0, 0.0, 0.0);
+        true
+    }
+
+    fn surface(&self) -> &Surface
+    {
+        &self.surface
+    }
+
+    fn fingerprint(&self, fingerprint: &mut checkpoint::Fingerprint)
+    {
+        self.boundary.fingerprint(fingerprint);
+        fingerprint.f32(self.density);
+        self.surface.fingerprint(fingerprint);
+    }
+}
+
+// Homogeneous fog filling a sphere around the scene. The sphere gives the
+// fog a finite extent so that rays which scatter out of the scene can
+// still reach the sky.
+pub struct Fog
+{
+    pub density: f32,
+    pub albedo: vec3f::Vec3f32,
+    pub phase: PhaseFunction,
+    pub centre: vec3f::Vec3f32,
+    pub radius: f32,
+}
+
+impl Fog
+{
+    // Range of the ray parameter spent inside the fog sphere
+    fn span(&self, r: &mut ray::Ray) -> Option<(f32, f32)>
+    {
+        let oc = r.origin() - self.centre;
+        let a = r.direction().dot_product(&r.direction());
+        let b = oc.dot_product(&r.direction());
+        let c = oc.dot_product(&oc) - self.radius * self.radius;
+        let discriminant = b * b - a * c;
+        if discriminant <= 0.0
+        {
+            return None;
+        }
+        let root = discriminant.sqrt();
+        Some(((-b - root) / a, (-b + root) / a))
+    }
+
+    // Samples where along the ray, between tmin and tmax, light scatters in
+    // the fog. None means it passes through to whatever lies at tmax.
+    pub fn sample_scatter(&self, r: &mut ray::Ray, tmin: f32,
+                          tmax: f32) -> Option<f32>
+    {
+        let (entry, exit) = self.span(r)?;
+        let start = entry.max(tmin);
+        let end = exit.min(tmax);
+        if start >= end
+        {
+            return None;
+        }
+        let t = start + sample_free_flight(self.density, r.direction().length());
+        if t < end
+        {
+            Some(t)
+        }
+        else
+        {
+            None
+        }
+    }
+
+    pub fn fingerprint(&self, fingerprint: &mut checkpoint::Fingerprint)
+    {
+        fingerprint.f32(self.density);
+        fingerprint.vec3(&self.albedo);
+        self.phase.fingerprint(fingerprint);
+        fingerprint.vec3(&self.centre);
+        fingerprint.f32(self.radius);
+    }
+}
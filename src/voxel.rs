@@ -0,0 +1,326 @@
+use std::fs;
+use std::io;
+
+use crate::vec3f;
+use crate::rng;
+use crate::ray;
+use crate::volume;
+use crate::checkpoint;
+use crate::hittable::{HitRecord, Hittable, Material, Surface};
+
+// A dense nx by ny by nz grid of values stored with x varying fastest,
+// sampled with trilinear interpolation between voxel centres
+pub struct VoxelGrid
+{
+    nx: usize,
+    ny: usize,
+    nz: usize,
+    values: Vec<f32>,
+    max_value: f32,
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32
+{
+    u32::from_le_bytes([data[offset], data[offset + 1],
+                        data[offset + 2], data[offset + 3]])
+}
+
+impl VoxelGrid
+{
+    pub fn new(nx: usize, ny: usize, nz: usize, values: Vec<f32>) -> VoxelGrid
+    {
+        assert!(nx >= 1 && ny >= 1 && nz >= 1, "a voxel grid cannot be empty");
+        assert_eq!(values.len(), nx * ny * nz);
+        let max_value = values.iter().cloned().fold(0.0, f32::max);
+        VoxelGrid
+        {
+            nx,
+            ny,
+            nz,
+            values,
+            max_value,
+        }
+    }
+
+    // Loads a grid from a raw binary file: the dimensions nx, ny and nz as
+    // little endian u32, followed by nx * ny * nz little endian f32 values
+    // with x varying fastest
+    pub fn from_file(path: &str) -> io::Result<VoxelGrid>
+    {
+        let data = fs::read(path)?;
+        if data.len() < 12
+        {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      "voxel file is missing its header"));
+        }
+        let nx = read_u32(&data, 0) as usize;
+        let ny = read_u32(&data, 4) as usize;
+        let nz = read_u32(&data, 8) as usize;
+        // The header is untrusted, so sizes that overflow are rejected
+        // rather than wrapped
+        let length = nx.checked_mul(ny)
+            .and_then(|n| n.checked_mul(nz))
+            .and_then(|count| count.checked_mul(4))
+            .and_then(|bytes| bytes.checked_add(12));
+        let Some(length) = length else
+        {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      "voxel file dimensions are too large"));
+        };
+        if length == 12 || data.len() != length
+        {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      "voxel file size does not match its header"));
+        }
+        let values = data[12 ..]
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        Ok(VoxelGrid::new(nx, ny, nz, values))
+    }
+
+    pub fn max_value(&self) -> f32
+    {
+        self.max_value
+    }
+
+    fn value(&self, i: usize, j: usize, k: usize) -> f32
+    {
+        self.values[(k * self.ny + j) * self.nx + i]
+    }
+
+    // Interpolated value at normalised grid coordinates in [0, 1]^3
+    pub fn sample(&self, u: f32, v: f32, w: f32) -> f32
+    {
+        let locate = |coord: f32, n: usize| -> (usize, usize, f32)
+        {
+            let x = (coord * n as f32 - 0.5).clamp(0.0, (n - 1) as f32);
+            let i0 = x.floor() as usize;
+            let i1 = (i0 + 1).min(n - 1);
+            (i0, i1, x - i0 as f32)
+        };
+        let (i0, i1, fx) = locate(u, self.nx);
+        let (j0, j1, fy) = locate(v, self.ny);
+        let (k0, k1, fz) = locate(w, self.nz);
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let c00 = lerp(self.value(i0, j0, k0), self.value(i1, j0, k0), fx);
+        let c10 = lerp(self.value(i0, j1, k0), self.value(i1, j1, k0), fx);
+        let c01 = lerp(self.value(i0, j0, k1), self.value(i1, j0, k1), fx);
+        let c11 = lerp(self.value(i0, j1, k1), self.value(i1, j1, k1), fx);
+        lerp(lerp(c00, c10, fy), lerp(c01, c11, fy), fz)
+    }
+
+    pub fn fingerprint(&self, fingerprint: &mut checkpoint::Fingerprint)
+    {
+        fingerprint.u32(self.nx as u32);
+        fingerprint.u32(self.ny as u32);
+        fingerprint.u32(self.nz as u32);
+        for &value in &self.values
+        {
+            fingerprint.f32(value);
+        }
+    }
+}
+
+// Approximate colour of a black body at the given temperature in kelvin,
+// fitted to the CIE colour matching functions (after Tanner Helland) and
+// brought back to linear values
+fn blackbody_colour(temperature: f32) -> vec3f::Vec3f32
+{
+    let t = temperature.clamp(1000.0, 40000.0) / 100.0;
+    let red = if t <= 66.0
+    {
+        255.0
+    }
+    else
+    {
+        329.698_73 * (t - 60.0).powf(-0.133_204_76)
+    };
+    let green = if t <= 66.0
+    {
+        99.470_8 * t.ln() - 161.119_57
+    }
+    else
+    {
+        288.122_17 * (t - 60.0).powf(-0.075_514_85)
+    };
+    let blue = if t >= 66.0
+    {
+        255.0
+    }
+    else if t <= 19.0
+    {
+        0.0
+    }
+    else
+    {
+        138.517_73 * (t - 10.0).ln() - 305.044_8
+    };
+    let srgb = vec3f::Vec3f32::new_from_points(red.clamp(0.0, 255.0),
+                                               green.clamp(0.0, 255.0),
+                                               blue.clamp(0.0, 255.0)) / 255.0;
+    srgb * srgb
+}
+
+// Light given off by a voxel medium
+pub enum Emission
+{
+    None,
+    // The same radiance everywhere the medium absorbs light
+    Uniform(vec3f::Vec3f32),
+    // A grid of temperatures in kelvin covering the same box as the density,
+    // emitting black body colours whose brightness grows with the fourth
+    // power of temperature
+    Temperature
+    {
+        temperatures: VoxelGrid,
+        scale: f32,
+    },
+}
+
+// A heterogeneous medium whose density is read from a voxel grid stretched
+// over the box from `low` to `high`, tracked with delta tracking against
+// the largest density in the grid
+pub struct VoxelMedium
+{
+    density: VoxelGrid,
+    density_scale: f32,
+    low: vec3f::Vec3f32,
+    high: vec3f::Vec3f32,
+    emission: Emission,
+    surface: Surface,
+}
+
+impl VoxelMedium
+{
+    pub fn new(density: VoxelGrid, density_scale: f32,
+               low: vec3f::Vec3f32, high: vec3f::Vec3f32,
+               albedo: vec3f::Vec3f32,
+               phase: volume::PhaseFunction) -> VoxelMedium
+    {
+        VoxelMedium
+        {
+            density,
+            density_scale,
+            low,
+            high,
+            emission: Emission::None,
+            surface: Surface
+            {
+                material: Material::Medium(phase),
+                albedo,
+                fuzz: 0.0,
+                refraction: 1.0,
+            },
+        }
+    }
+
+    pub fn with_emission(mut self, emission: Emission) -> VoxelMedium
+    {
+        self.emission = emission;
+        self
+    }
+
+    fn grid_coordinates(&self, p: &vec3f::Vec3f32) -> (f32, f32, f32)
+    {
+        ((p.x - self.low.x) / (self.high.x - self.low.x),
+         (p.y - self.low.y) / (self.high.y - self.low.y),
+         (p.z - self.low.z) / (self.high.z - self.low.z))
+    }
+
+    // Radiance Le given off where light is absorbed. Collisions are already
+    // found in proportion to density, so it is not scaled by density again.
+    fn emitted(&self, u: f32, v: f32, w: f32) -> vec3f::Vec3f32
+    {
+        match self.emission
+        {
+            Emission::None => vec3f::Vec3f32::zeroes(),
+            Emission::Uniform(radiance) => radiance,
+            Emission::Temperature { ref temperatures, scale } =>
+            {
+                let temperature = temperatures.sample(u, v, w);
+                if temperature <= 0.0
+                {
+                    return vec3f::Vec3f32::zeroes();
+                }
+                blackbody_colour(temperature) * (scale * (temperature / 1000.0).powi(4))
+            }
+        }
+    }
+}
+
+impl Hittable for VoxelMedium
+{
+    fn hit(&self, r: &mut ray::Ray, tmin: f32, tmax: f32,
+           rec: &mut HitRecord) -> bool
+    {
+        let majorant = self.density.max_value() * self.density_scale;
+        if majorant <= 0.0
+        {
+            return false;
+        }
+        let (start, end) = match r.box_span(&self.low, &self.high, tmin, tmax)
+        {
+            Some(span) => span,
+            None => return false,
+        };
+
+        // Delta tracking: take free-flight steps through a homogeneous
+        // medium of the majorant density and accept each tentative
+        // collision with probability density / majorant
+        let length = r.direction().length();
+        let mut t = start;
+        loop
+        {
+            t -= (1.0 - rng::random()).ln() / (majorant * length);
+            if t >= end
+            {
+                return false;
+            }
+            let p = r.point_at_parameter(&t);
+            let (u, v, w) = self.grid_coordinates(&p);
+            let density = self.density.sample(u, v, w) * self.density_scale;
+            if rng::random() * majorant < density
+            {
+                rec.t = t;
+                rec.p = p;
+                rec.normal = vec3f::Vec3f32::new_from_points(1.0, 0.0, 0.0);
+                // Collision estimator: the share of collisions that are
+                // absorptions contributes (1 - albedo) Le
+                let absorption = vec3f::Vec3f32::new_from_points(1.0, 1.0, 1.0)
+                    - self.surface.albedo;
+                rec.emitted = absorption * self.emitted(u, v, w);
+                return true;
+            }
+        }
+    }
+
+    fn surface(&self) -> &Surface
+    {
+        &self.surface
+    }
+
+    fn fingerprint(&self, fingerprint: &mut checkpoint::Fingerprint)
+    {
+        self.density.fingerprint(fingerprint);
+        fingerprint.f32(self.density_scale);
+        fingerprint.vec3(&self.low);
+        fingerprint.vec3(&self.high);
+        match self.emission
+        {
+            Emission::None => fingerprint.u32(0),
+            Emission::Uniform(ref radiance) =>
+            {
+                fingerprint.u32(1);
+                fingerprint.vec3(radiance);
+            },
+            Emission::Temperature { ref temperatures, scale } =>
+            {
+                fingerprint.u32(2);
+                temperatures.fingerprint(fingerprint);
+                fingerprint.f32(scale);
+            },
+        }
+        self.surface.fingerprint(fingerprint);
+    }
+}